@@ -1,7 +1,9 @@
 MEMORY {
-    BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
-    RAM   : ORIGIN = 0x20000000, LENGTH = 256K
+    BOOT2  : ORIGIN = 0x10000000, LENGTH = 0x100
+    FLASH  : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 16K
+    /* The settings store; must match `hardware::flash::STORE_OFFSET` and `Store::SIZE` */
+    CONFIG : ORIGIN = 0x10000000 + 2048K - 16K, LENGTH = 16K
+    RAM    : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
 EXTERN(BOOT2_FIRMWARE)
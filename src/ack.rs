@@ -0,0 +1,186 @@
+//! The acknowledgement of processed commands
+
+use crate::const_int_from_compileenv;
+
+/// The acknowledgement mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckMode {
+    /// Echoes each command line or frame header
+    Echo,
+    /// Sends a compact sequence-numbered acknowledgement for each command
+    Compact,
+    /// Sends a compact sequence-numbered acknowledgement for every n-th command
+    Every(u16),
+    /// Sends no acknowledgements
+    Silent,
+}
+impl AckMode {
+    /// The acknowledgement mode from the compile-time environment
+    pub const COMPILE_ENV: Self = {
+        /// The mode code
+        const MODE: u8 = const_int_from_compileenv!("WS2812B_ACK" => u8, default: "0");
+        /// The interval of the compact acknowledgements if the mode code is `2`
+        const INTERVAL: u16 = const_int_from_compileenv!("WS2812B_ACK_INTERVAL" => u16, default: "1");
+        crate::const_unwrap!(Self::from_code(MODE, INTERVAL), "invalid acknowledgement mode")
+    };
+
+    /// Decodes an acknowledgement mode command line
+    ///
+    /// # Format
+    /// The command line is either `ACK ECHO\n`, `ACK COMPACT\n`, `ACK SILENT\n` or `ACK EVERY <n>\n`, where `n` is a
+    /// decimal number within `[1, 65535]`.
+    pub fn from_serial(data: &[u8]) -> Option<Self> {
+        match data {
+            b"ACK ECHO\n" => Some(Self::Echo),
+            b"ACK COMPACT\n" => Some(Self::Compact),
+            b"ACK SILENT\n" => Some(Self::Silent),
+            line => {
+                let digits = line.strip_prefix(b"ACK EVERY ")?.strip_suffix(b"\n")?;
+                let interval = core::str::from_utf8(digits).ok()?.parse().ok()?;
+                Self::from_code(2, interval)
+            }
+        }
+    }
+
+    /// Decodes an acknowledgement mode frame body
+    ///
+    /// # Format
+    /// The body is either a single byte, which is `0` (echo), `1` (compact) or `3` (silent), or the byte `2` followed
+    /// by the big-endian interval of the compact acknowledgements.
+    pub fn from_frame(data: &[u8]) -> Option<Self> {
+        match *data {
+            [code @ (0 | 1 | 3)] => Self::from_code(code, 0),
+            [2, high, low] => Self::from_code(2, u16::from_be_bytes([high, low])),
+            _ => None,
+        }
+    }
+
+    /// Decodes the mode from its code, where the interval is only used by the interval mode and must be non-zero
+    const fn from_code(code: u8, interval: u16) -> Option<Self> {
+        match (code, interval) {
+            (0, _) => Some(Self::Echo),
+            (1, _) => Some(Self::Compact),
+            (2, 1..) => Some(Self::Every(interval)),
+            (3, _) => Some(Self::Silent),
+            _ => None,
+        }
+    }
+}
+
+/// The acknowledgement to send for a processed command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ack {
+    /// Echo the command line or frame header
+    Echo,
+    /// Send the compact acknowledgement
+    Compact([u8; Acknowledger::SERIAL_LEN]),
+    /// Send nothing
+    None,
+}
+
+/// Counts the processed commands and decides which acknowledgement to send
+///
+/// # Format
+/// A compact acknowledgement has a fixed size of 3 bytes (indices are byte offsets):
+///  - `[0]`: The ASCII acknowledgement byte `0x06`
+///  - `[1, 3)`: The big-endian sequence number of the acknowledged command, which counts all processed commands since
+///    boot and wraps around
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Acknowledger {
+    /// The acknowledgement mode
+    mode: AckMode,
+    /// The sequence number of the last processed command
+    sequence: u16,
+    /// The amount of processed commands since the last compact acknowledgement in interval mode
+    skipped: u16,
+}
+impl Acknowledger {
+    /// The first byte of a compact acknowledgement
+    pub const ACK: u8 = 0x06;
+    /// The size of a serial compact acknowledgement
+    pub const SERIAL_LEN: usize = 3;
+
+    /// Creates a new acknowledger
+    pub const fn new(mode: AckMode) -> Self {
+        Self { mode, sequence: 0, skipped: 0 }
+    }
+
+    /// Sets the acknowledgement mode, where the interval of the interval mode starts with the next command
+    pub fn set_mode(&mut self, mode: AckMode) {
+        self.mode = mode;
+        self.skipped = 0;
+    }
+
+    /// Registers a processed command and returns the acknowledgement to send
+    pub fn register(&mut self) -> Ack {
+        self.sequence = self.sequence.wrapping_add(1);
+        let [high, low] = self.sequence.to_be_bytes();
+        match self.mode {
+            AckMode::Echo => Ack::Echo,
+            AckMode::Compact => Ack::Compact([Self::ACK, high, low]),
+            AckMode::Every(interval) => {
+                // Note: The interval is counted separately, since the sequence number wraps around
+                self.skipped += 1;
+                if self.skipped < interval {
+                    return Ack::None;
+                }
+                self.skipped = 0;
+                Ack::Compact([Self::ACK, high, low])
+            }
+            AckMode::Silent => Ack::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_are_decoded() {
+        assert_eq!(AckMode::from_serial(b"ACK ECHO\n"), Some(AckMode::Echo));
+        assert_eq!(AckMode::from_serial(b"ACK COMPACT\n"), Some(AckMode::Compact));
+        assert_eq!(AckMode::from_serial(b"ACK SILENT\n"), Some(AckMode::Silent));
+        assert_eq!(AckMode::from_serial(b"ACK EVERY 65535\n"), Some(AckMode::Every(65535)));
+        assert_eq!(AckMode::from_serial(b"ACK EVERY 0\n"), None);
+        assert_eq!(AckMode::from_serial(b"ACK EVERY 1x\n"), None);
+        assert_eq!(AckMode::from_serial(b"xxACK ECHO\n"), None);
+
+        assert_eq!(AckMode::from_frame(&[2, 0x01, 0x00]), Some(AckMode::Every(256)));
+        assert_eq!(AckMode::from_frame(&[2, 0, 0]), None);
+        assert_eq!(AckMode::from_frame(&[3]), Some(AckMode::Silent));
+    }
+
+    #[test]
+    fn acknowledgements_follow_the_mode() {
+        let mut acks = Acknowledger::new(AckMode::Echo);
+        assert_eq!(acks.register(), Ack::Echo);
+
+        acks.set_mode(AckMode::Compact);
+        assert_eq!(acks.register(), Ack::Compact([Acknowledger::ACK, 0, 2]));
+
+        acks.set_mode(AckMode::Every(3));
+        let sent: Vec<_> = (0..6).map(|_| acks.register()).filter(|ack| *ack != Ack::None).collect();
+        assert_eq!(sent, [Ack::Compact([Acknowledger::ACK, 0, 5]), Ack::Compact([Acknowledger::ACK, 0, 8])]);
+
+        acks.set_mode(AckMode::Silent);
+        assert_eq!(acks.register(), Ack::None);
+    }
+
+    #[test]
+    fn intervals_are_kept_across_the_sequence_wrap() {
+        let mut acks = Acknowledger::new(AckMode::Every(1000));
+        let sent: Vec<_> = (0..70_000u32)
+            .map(|count| (count + 1, acks.register()))
+            .filter_map(|(count, ack)| match ack {
+                Ack::Compact([Acknowledger::ACK, high, low]) => Some((count, u16::from_be_bytes([high, low]))),
+                _ => None,
+            })
+            .collect();
+
+        // 65536 is not a multiple of 1000, so the acknowledgements after the wrap must still be 1000 commands apart
+        assert_eq!(sent.len(), 70);
+        assert!(sent.iter().all(|(count, _)| count % 1000 == 0));
+        assert_eq!(sent[65], (66_000, 464));
+    }
+}
@@ -0,0 +1,195 @@
+//! The Adalight and AWA ambilight protocols
+
+/// The ambilight protocol of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// The Adalight protocol, which has no checksum for the pixel data
+    Adalight,
+    /// The AWA protocol, which appends a Fletcher checksum to the pixel data
+    Awa {
+        /// Whether the pixel data is followed by the 4 byte white channel calibration
+        calibration: bool,
+    },
+}
+
+/// The header of an Adalight or AWA frame
+///
+/// # Format
+/// Each frame starts with a 6 byte header (indices are byte offsets), followed by the RGB values of each LED:
+///  - `[0, 3)`: The magic bytes `Ada` (Adalight), `Awa` (AWA) or `AwA` (AWA with white channel calibration)
+///  - `[3, 5)`: The big-endian amount of LEDs minus one
+///  - `[5]`: The header checksum, which is `count_high ^ count_low ^ 0x55`
+///
+/// AWA frames are terminated by the 4 byte white channel calibration (if enabled) and the 3 byte Fletcher checksum of
+/// the pixel data and the calibration (see `Fletcher`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmbilightHeader {
+    /// The protocol
+    pub protocol: Protocol,
+    /// The amount of LEDs
+    pub count: usize,
+}
+impl AmbilightHeader {
+    /// The first byte of the magic bytes
+    pub const MAGIC: u8 = b'A';
+    /// The size of the magic bytes
+    pub const MAGIC_LEN: usize = 3;
+    /// The magic bytes of all supported protocols
+    const MAGICS: [[u8; Self::MAGIC_LEN]; 3] = [*b"Ada", *b"Awa", *b"AwA"];
+    /// The size of a serial ambilight header
+    pub const SERIAL_LEN: usize = 6;
+    /// The size of the white channel calibration
+    const CALIBRATION_LEN: usize = 4;
+    /// The maximum size of the frame trailer
+    pub const TRAILER_LEN_MAX: usize = Self::CALIBRATION_LEN + Fletcher::SERIAL_LEN;
+
+    /// Decodes a serial ambilight header
+    pub fn from_serial(data: &[u8]) -> Option<Self> {
+        let [Self::MAGIC, magic1, magic2, count_high, count_low, checksum] = *data else {
+            return None;
+        };
+
+        // Validate the header checksum and decode the protocol
+        if count_high ^ count_low ^ 0x55 != checksum {
+            return None;
+        }
+        let protocol = match (magic1, magic2) {
+            (b'd', b'a') => Protocol::Adalight,
+            (b'w', b'a') => Protocol::Awa { calibration: false },
+            (b'w', b'A') => Protocol::Awa { calibration: true },
+            _ => return None,
+        };
+
+        // Note: The header contains the amount of LEDs minus one
+        let count = u16::from_be_bytes([count_high, count_low]) as usize + 1;
+        Some(Self { protocol, count })
+    }
+
+    /// Whether the data is the beginning of the magic bytes of a supported protocol
+    ///
+    /// # Important
+    /// Command lines may also start with `A`, so a frame can only be told apart from a command line once all magic
+    /// bytes have been received.
+    pub fn is_magic(data: &[u8]) -> bool {
+        Self::MAGICS.iter().any(|magic| magic.starts_with(data))
+    }
+
+    /// The size of the frame trailer after the pixel data
+    pub const fn trailer_len(&self) -> usize {
+        match self.protocol {
+            Protocol::Adalight => 0,
+            Protocol::Awa { calibration: false } => Fletcher::SERIAL_LEN,
+            Protocol::Awa { calibration: true } => Self::CALIBRATION_LEN + Fletcher::SERIAL_LEN,
+        }
+    }
+}
+
+/// The Fletcher checksum of the AWA protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fletcher {
+    /// The sum of all bytes
+    sum1: u16,
+    /// The sum of all `sum1` values
+    sum2: u16,
+    /// The sum of all bytes XOR-ed with their truncated position
+    sum_ext: u16,
+    /// The truncated position of the next byte
+    position: u8,
+}
+impl Fletcher {
+    /// The size of a serial checksum
+    pub const SERIAL_LEN: usize = 3;
+
+    /// Creates a new checksum
+    pub const fn new() -> Self {
+        Self { sum1: 0, sum2: 0, sum_ext: 0, position: 0 }
+    }
+
+    /// Adds the given bytes to the checksum
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.sum1 = (self.sum1 + *byte as u16) % 255;
+            self.sum2 = (self.sum2 + self.sum1) % 255;
+            self.sum_ext = (self.sum_ext + (*byte ^ self.position) as u16) % 255;
+            self.position = self.position.wrapping_add(1);
+        }
+    }
+
+    /// Encodes the checksum into its serial representation
+    ///
+    /// # Note
+    /// An extended checksum of `0x41` is replaced with `0xAA`, so that the checksum can never be mistaken for the start
+    /// of the next frame.
+    pub const fn to_serial(&self) -> [u8; Self::SERIAL_LEN] {
+        let sum_ext = match self.sum_ext {
+            0x41 => 0xAA,
+            sum_ext => sum_ext as u8,
+        };
+        [self.sum1 as u8, self.sum2 as u8, sum_ext]
+    }
+
+    /// Adds the frame trailer except for the checksum to the checksum, and compares it against the trailing checksum
+    pub fn verify(mut self, trailer: &[u8]) -> bool {
+        let Some(split) = trailer.len().checked_sub(Self::SERIAL_LEN) else {
+            return false;
+        };
+        let (calibration, checksum) = trailer.split_at(split);
+        self.update(calibration);
+        self.to_serial() == checksum
+    }
+}
+impl Default for Fletcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_are_decoded() {
+        let adalight = AmbilightHeader::from_serial(b"Ada\x01\x2B\x7F").expect("failed to decode Adalight header");
+        assert_eq!(adalight, AmbilightHeader { protocol: Protocol::Adalight, count: 300 });
+        assert_eq!(adalight.trailer_len(), 0);
+
+        let awa = AmbilightHeader::from_serial(b"AwA\x00\x00\x55").expect("failed to decode AWA header");
+        assert_eq!(awa, AmbilightHeader { protocol: Protocol::Awa { calibration: true }, count: 1 });
+        assert_eq!(awa.trailer_len(), 7);
+
+        assert_eq!(AmbilightHeader::from_serial(b"Ada\x01\x2B\x7E"), None);
+        assert_eq!(AmbilightHeader::from_serial(b"Adb\x00\x00\x55"), None);
+    }
+
+    #[test]
+    fn magic_bytes_are_matched_entirely() {
+        assert!(AmbilightHeader::is_magic(b"A"));
+        assert!(AmbilightHeader::is_magic(b"Aw"));
+        assert!(AmbilightHeader::is_magic(b"AwA"));
+        assert!(!AmbilightHeader::is_magic(b"AC"));
+        assert!(!AmbilightHeader::is_magic(b"Adb"));
+    }
+
+    #[test]
+    fn fletcher_checksum_matches_awa() {
+        let mut fletcher = Fletcher::new();
+        fletcher.update(&[0; 3]);
+        assert_eq!(fletcher.to_serial(), [0, 0, 3]);
+
+        // The extended checksum `0x41` is escaped
+        let mut fletcher = Fletcher::new();
+        fletcher.update(&[0x41]);
+        assert_eq!(fletcher.to_serial(), [0x41, 0x41, 0xAA]);
+
+        // The calibration is part of the checksum
+        let mut fletcher = Fletcher::new();
+        fletcher.update(&[0xFF, 0x80, 0x01]);
+        let mut expected = fletcher;
+        expected.update(&[1, 2, 3, 4]);
+        let [sum1, sum2, sum_ext] = expected.to_serial();
+        assert!(fletcher.verify(&[1, 2, 3, 4, sum1, sum2, sum_ext]));
+        assert!(!fletcher.verify(&[1, 2, 3, 4, sum1, sum2, sum_ext ^ 1]));
+        assert!(!fletcher.verify(&[sum1, sum2]));
+    }
+}
@@ -0,0 +1,123 @@
+//! The global and per-strip brightness
+
+use crate::command::Color;
+use crate::config::StripConfig;
+
+/// The brightness levels that are applied while encoding the pixels
+///
+/// # Note
+/// The effective brightness of a strip is the product of the global and the strip brightness, which is clamped to the
+/// brightness limit of the persistent settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Brightness {
+    /// The global brightness
+    pub global: u8,
+    /// The brightness of each strip
+    pub strips: [u8; StripConfig::STRIPS],
+    /// The upper limit for the effective brightness
+    pub limit: u8,
+}
+impl Brightness {
+    /// The smallest 16 bit channel value that narrows to a non-zero 8 bit value
+    const VISIBLE: u16 = 0x81;
+
+    /// Creates a new brightness register with full brightness and the given limit
+    pub const fn new(limit: u8) -> Self {
+        Self { global: u8::MAX, strips: [u8::MAX; StripConfig::STRIPS], limit }
+    }
+
+    /// Sets the brightness of the given strip, or the global brightness if `strip` is `None`
+    ///
+    /// # Important
+    /// Strip indices that are out of range are silently ignored.
+    pub fn set(&mut self, strip: Option<usize>, level: u8) {
+        let slot = match strip {
+            Some(strip) => self.strips.get_mut(strip),
+            None => Some(&mut self.global),
+        };
+        if let Some(slot) = slot {
+            *slot = level;
+        }
+    }
+
+    /// The effective brightness of the given strip
+    pub const fn level(&self, strip: usize) -> u8 {
+        let level = Self::scale8(self.global, self.strips[strip]);
+        match level > self.limit {
+            true => self.limit,
+            false => level,
+        }
+    }
+
+    /// Scales a 16 bit RGBW value by the given brightness
+    pub const fn apply([r, g, b, w]: [u16; 4], level: u8) -> [u16; 4] {
+        [Self::scale(r, level), Self::scale(g, level), Self::scale(b, level), Self::scale(w, level)]
+    }
+
+    /// Scales a 16 bit channel value by the given brightness, rounded to the nearest value
+    ///
+    /// # Note
+    /// Channels that are visible after narrowing stay visible for any non-zero brightness, so that dim levels do not
+    /// collapse to black.
+    pub const fn scale(value: u16, level: u8) -> u16 {
+        let scaled = ((value as u32 * level as u32) + 127) / 255;
+        match scaled as u16 {
+            scaled if scaled < Self::VISIBLE && level > 0 && Color::narrow(value) > 0 => Self::VISIBLE,
+            scaled => scaled,
+        }
+    }
+
+    /// Scales an 8 bit brightness by another brightness, where non-zero levels stay non-zero
+    const fn scale8(level: u8, factor: u8) -> u8 {
+        let scaled = ((level as u16 * factor as u16) + 127) / 255;
+        match scaled as u8 {
+            0 if level > 0 && factor > 0 => 1,
+            scaled => scaled,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_brightness_is_lossless() {
+        for value in 0..=u16::MAX {
+            assert_eq!(Brightness::scale(value, u8::MAX), value);
+        }
+    }
+
+    #[test]
+    fn scaling_is_rounded() {
+        assert_eq!(Brightness::scale(0xFFFF, 128), 0x8080);
+        assert_eq!(Brightness::scale(0x1000, 51), 0x0333);
+        assert_eq!(Brightness::scale(0xFFFF, 0), 0);
+        assert_eq!(Brightness::scale(0, 200), 0);
+    }
+
+    #[test]
+    fn dim_levels_stay_visible() {
+        for value in 1..=u8::MAX {
+            for level in 1..=u8::MAX {
+                let scaled = Brightness::scale(Color::Rgbw8([value; 4]).to_rgbw16()[0], level);
+                assert_ne!(Color::narrow(scaled), 0, "value {value} vanished at level {level}");
+            }
+        }
+    }
+
+    #[test]
+    fn levels_are_combined_and_limited() {
+        let mut brightness = Brightness::new(200);
+        assert_eq!(brightness.level(0), 200);
+
+        brightness.set(None, 128);
+        brightness.set(Some(1), 128);
+        assert_eq!((brightness.level(0), brightness.level(1)), (128, 64));
+
+        brightness.set(Some(1), 1);
+        assert_eq!(brightness.level(1), 1);
+        brightness.set(Some(StripConfig::STRIPS), 0);
+        assert_eq!((brightness.level(0), brightness.level(1)), (128, 1));
+    }
+}
@@ -1,43 +1,114 @@
 //! A WS2812B pixel update command
 
-/// A WS2812B pixel update command that can be compressed into 32 bits
-///
-/// # Important
-/// To compress all values into a single `u32`, the format has some constraints, namely:
-///  - only 4 strip indices (`0..=3`)
-///  - only 512 pixel indices (`0..=511`)
-///  - `r`, `g` and `b` are compressed to 7 bits by setting the least-significant bit to `0`
-#[derive(Debug, Clone, Copy)]
+use crate::config::StripConfig;
+
+/// An RGBW color value with either 8 or 16 bit per channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// An RGBW value with 8 bit per channel
+    Rgbw8([u8; 4]),
+    /// An RGBW value with 16 bit per channel
+    Rgbw16([u16; 4]),
+}
+impl Color {
+    /// Gets the white value
+    pub const fn white(self) -> u16 {
+        match self {
+            Self::Rgbw8([_, _, _, w]) => w as u16,
+            Self::Rgbw16([_, _, _, w]) => w,
+        }
+    }
+
+    /// Expands the color to 16 bit per channel
+    ///
+    /// # Note
+    /// 8 bit values are scaled by `257`, so that `0xFF` maps to `0xFFFF` and `narrow` restores the original value.
+    pub const fn to_rgbw16(self) -> [u16; 4] {
+        match self {
+            Self::Rgbw8([r, g, b, w]) => [r as u16 * 257, g as u16 * 257, b as u16 * 257, w as u16 * 257],
+            Self::Rgbw16(rgbw) => rgbw,
+        }
+    }
+
+    /// Narrows a 16 bit channel value to 8 bit by rounding to the nearest value
+    pub const fn narrow(value: u16) -> u8 {
+        ((value as u32 * 255 + 32767) / 65535) as u8
+    }
+}
+
+/// The reason why a serial command was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The command line is not a 16 digit hex literal
+    InvalidHex,
+    /// The strip index is out of range, or the strip is disabled
+    InvalidStrip,
+    /// The pixel index exceeds the strip length
+    InvalidPixel,
+    /// The white value is not `0`, but the strip is not an RGBW strip
+    InvalidWhite,
+    /// The command line is longer than a serial command or the line buffer
+    Overlong,
+    /// The frame length or the batch header of a binary frame is malformed
+    InvalidFrame,
+}
+impl DecodeError {
+    /// The size of a serial error prefix
+    pub const SERIAL_LEN: usize = 6;
+
+    /// The numeric error code
+    pub const fn code(self) -> u8 {
+        match self {
+            Self::InvalidHex => 1,
+            Self::InvalidStrip => 2,
+            Self::InvalidPixel => 3,
+            Self::InvalidWhite => 4,
+            Self::Overlong => 5,
+            Self::InvalidFrame => 6,
+        }
+    }
+
+    /// Encodes the error into the serial prefix `ERR <code> `, which is followed by the rejected command line or frame
+    /// header
+    pub const fn to_serial(self) -> [u8; Self::SERIAL_LEN] {
+        [b'E', b'R', b'R', b' ', b'0' + self.code(), b' ']
+    }
+}
+
+/// A WS2812B pixel update command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Command {
     /// The index of the WS2812B strip to update
     pub strip: usize,
     /// The index of the pixel to update
     pub pixel: usize,
-    /// The new RGB value
-    pub rgb: (u8, u8, u8),
+    /// The new RGBW value
+    pub color: Color,
 }
 impl Command {
     /// The size of a serial command
     pub const SERIAL_LEN: usize = 16 + 1;
 
-    /// Decodes a serial command
-    pub fn from_serial(data: &[u8]) -> Option<Self> {
+    /// Decodes a serial command line including the newline and validates it against the given strip configurations
+    pub fn from_serial(data: &[u8], configs: &[StripConfig]) -> Result<Self, DecodeError> {
         /// Decodes a nibble from it's hex representation
         #[inline]
-        const fn decode_nibble(nibble: u8) -> Option<u8> {
+        const fn decode_nibble(nibble: u8) -> Result<u8, DecodeError> {
             #[allow(clippy::identity_op)]
             match nibble {
-                b'0'..=b'9' => Some(nibble - (b'0' - 0x0)),
-                b'a'..=b'f' => Some(nibble - (b'a' - 0xa)),
-                b'A'..=b'F' => Some(nibble - (b'A' - 0xA)),
-                _ => None,
+                b'0'..=b'9' => Ok(nibble - (b'0' - 0x0)),
+                b'a'..=b'f' => Ok(nibble - (b'a' - 0xa)),
+                b'A'..=b'F' => Ok(nibble - (b'A' - 0xA)),
+                _ => Err(DecodeError::InvalidHex),
             }
         }
 
         // Validate length and EOL
-        let Some(b'\n') = data.get(Self::SERIAL_LEN - 1) else {
-            return None;
-        };
+        match data {
+            data if data.len() > Self::SERIAL_LEN => return Err(DecodeError::Overlong),
+            [.., b'\n'] if data.len() == Self::SERIAL_LEN => (),
+            _ => return Err(DecodeError::InvalidHex),
+        }
 
         // Hex-decode the bytes
         // Note: We use direct indexing here for performance reasons because the decoding routine is a bottle-neck
@@ -55,55 +126,89 @@ impl Command {
         let pixel = ((binary[2] as u16) << 8) | (binary[3] as u16);
         let rgbw = [binary[4], binary[5], binary[6], binary[7]];
 
+        // Validate data and init self
+        Self::validate(strip as usize, pixel as usize, Color::Rgbw8(rgbw), configs)
+    }
+
+    /// Creates a new command if the strip and pixel indices and the RGBW value are valid for the given strip
+    /// configurations
+    ///
+    /// # Important
+    /// The strip must be enabled, the pixel index must be within the strip length, and the white value must be `0` if
+    /// the strip is not an RGBW strip.
+    pub const fn new(strip: usize, pixel: usize, color: Color, configs: &[StripConfig]) -> Option<Self> {
+        match Self::validate(strip, pixel, color, configs) {
+            Ok(command) => Some(command),
+            Err(_) => None,
+        }
+    }
+
+    /// Creates a new command like `Command::new`, but returns the reason if the command is invalid
+    pub const fn validate(
+        strip: usize,
+        pixel: usize,
+        color: Color,
+        configs: &[StripConfig],
+    ) -> Result<Self, DecodeError> {
         // Validate data
-        let strip @ 0..=3 = strip else {
-            return None;
-        };
-        let pixel @ 0..=511 = pixel else {
-            return None;
-        };
-        let [r, g, b, 0] = rgbw else {
-            return None;
-        };
+        if strip >= configs.len() || !configs[strip].enabled {
+            return Err(DecodeError::InvalidStrip);
+        }
+        let config = configs[strip];
+        if pixel >= config.len {
+            return Err(DecodeError::InvalidPixel);
+        }
+        if color.white() != 0 && !config.rgbw {
+            return Err(DecodeError::InvalidWhite);
+        }
 
         // Init self
-        Some(Self { strip: strip as usize, pixel: pixel as usize, rgb: (r, g, b) })
+        Ok(Self { strip, pixel, color })
+    }
+
+    /// Creates an 8 bit RGB update for the LED with the given linear index across all enabled strips (see
+    /// `StripConfig::locate`)
+    pub fn linear(index: usize, [red, green, blue]: [u8; 3], configs: &[StripConfig]) -> Option<Self> {
+        let (strip, pixel) = StripConfig::locate(configs, index)?;
+        Self::new(strip, pixel, Color::Rgbw8([red, green, blue, 0]), configs)
     }
+}
 
-    /// Parses the command from it's packed `u32` representation
-    pub const fn from_u32(packed: u32) -> Self {
-        // Destructure u32 blob
-        let strip = (packed >> 30) & 0b11;
-        let pixel = (packed >> 21) & 0b1_1111_1111;
-        let red = (packed >> 14) & 0b0111_1111;
-        let green = (packed >> 7) & 0b0111_1111;
-        let blue = packed & 0b0111_1111;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // Init self
-        Self {
-            // Convert strip and pixel to usize indices
-            strip: strip as usize,
-            pixel: pixel as usize,
-            // We use the left-shift because we killed the least-significant bit during compression
-            rgb: ((red << 1) as u8, (green << 1) as u8, (blue << 1) as u8),
-        }
+    /// The configurations of a RGB and a disabled RGBW strip
+    const CONFIGS: [StripConfig; 2] =
+        [StripConfig::new(16, false), StripConfig { enabled: false, ..StripConfig::new(16, true) }];
+
+    #[test]
+    fn valid_lines_are_decoded() {
+        let expected = Command { strip: 0, pixel: 15, color: Color::Rgbw8([0xAB, 0xCD, 0xEF, 0]) };
+        assert_eq!(Command::from_serial(b"0000000fabcdef00\n", &CONFIGS), Ok(expected));
+        assert_eq!(Command::from_serial(b"0000000FABCDEF00\n", &CONFIGS), Ok(expected));
     }
-    /// Serializes the command into it's packed `u32` representation
-    ///
-    /// # Important
-    /// If `self.strip` is larger than `STRIP_INDEX_MAX` or if `self.pixel` is larger than `PIXEL_INDEX_MAX`, the values
-    /// are silently truncated.
-    pub const fn to_u32(self) -> u32 {
-        // Compress rgb values
-        let (r, g, b) = self.rgb;
-        let (r, g, b) = (r >> 1, g >> 1, b >> 1);
-
-        // Pack the struct
-        let mut packed = ((self.strip & 0b11) as u32) << 30;
-        packed |= ((self.pixel & 0b1_1111_1111) as u32) << 21;
-        packed |= ((r & 0b0111_1111) as u32) << 14;
-        packed |= ((g & 0b0111_1111) as u32) << 7;
-        packed |= (b & 0b0111_1111) as u32;
-        packed
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        assert_eq!(Command::from_serial(b"0000000gabcdef00\n", &CONFIGS), Err(DecodeError::InvalidHex));
+        assert_eq!(Command::from_serial(b"\00000000fabcdef0\n", &CONFIGS), Err(DecodeError::InvalidHex));
+        assert_eq!(Command::from_serial(b"0000000fabcdef000", &CONFIGS), Err(DecodeError::InvalidHex));
+        assert_eq!(Command::from_serial(b"0000000fabcdef\n", &CONFIGS), Err(DecodeError::InvalidHex));
+        assert_eq!(Command::from_serial(b"00000000fabcdef00\n", &CONFIGS), Err(DecodeError::Overlong));
+    }
+
+    #[test]
+    fn invalid_commands_are_rejected() {
+        assert_eq!(Command::from_serial(b"0001000000000000\n", &CONFIGS), Err(DecodeError::InvalidStrip));
+        assert_eq!(Command::from_serial(b"0002000000000000\n", &CONFIGS), Err(DecodeError::InvalidStrip));
+        assert_eq!(Command::from_serial(b"0000001000000000\n", &CONFIGS), Err(DecodeError::InvalidPixel));
+        assert_eq!(Command::from_serial(b"0000000000000001\n", &CONFIGS), Err(DecodeError::InvalidWhite));
+    }
+
+    #[test]
+    fn errors_are_encoded() {
+        assert_eq!(&DecodeError::InvalidHex.to_serial(), b"ERR 1 ");
+        assert_eq!(&DecodeError::Overlong.to_serial(), b"ERR 5 ");
     }
 }
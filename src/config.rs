@@ -0,0 +1,525 @@
+//! The LED strip configuration
+
+use crate::command::Color;
+use crate::const_int_from_compileenv;
+
+/// The order in which the color channels are shifted out to the LED strip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorOrder {
+    /// Green, red, blue (e.g. WS2812B)
+    Grb,
+    /// Red, green, blue (e.g. WS2811)
+    Rgb,
+    /// Blue, red, green
+    Brg,
+    /// Red, blue, green
+    Rbg,
+    /// Green, blue, red
+    Gbr,
+    /// Blue, green, red
+    Bgr,
+}
+impl ColorOrder {
+    /// Decodes the color order from its serial representation
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Grb),
+            1 => Some(Self::Rgb),
+            2 => Some(Self::Brg),
+            3 => Some(Self::Rbg),
+            4 => Some(Self::Gbr),
+            5 => Some(Self::Bgr),
+            _ => None,
+        }
+    }
+
+    /// Encodes the color order into its serial representation
+    pub const fn to_u8(self) -> u8 {
+        match self {
+            Self::Grb => 0,
+            Self::Rgb => 1,
+            Self::Brg => 2,
+            Self::Rbg => 3,
+            Self::Gbr => 4,
+            Self::Bgr => 5,
+        }
+    }
+}
+
+/// The timing profile of the LED chipset
+///
+/// # Format
+/// The serial representation has a fixed size of 7 bytes (indices are byte offsets):
+///  - `[0]`: The timing profile; `0` (WS2812B), `1` (WS2811), `2` (SK6812), `3` (WS2813) or `255` (custom)
+///  - `[1, 3)`: The big-endian high time of a zero bit in nanoseconds (for custom timings only)
+///  - `[3, 5)`: The big-endian high time of a one bit in nanoseconds (for custom timings only)
+///  - `[5, 7)`: The big-endian low time of a one bit in nanoseconds (for custom timings only)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    /// The WS2812B timing with 800 kHz
+    Ws2812b,
+    /// The WS2811 timing with 400 kHz
+    Ws2811,
+    /// The SK6812 timing with 800 kHz
+    Sk6812,
+    /// The WS2813 timing with 950 kHz
+    Ws2813,
+    /// A custom timing
+    Custom {
+        /// The high time of a zero bit in nanoseconds
+        t0h: u16,
+        /// The high time of a one bit in nanoseconds
+        t1h: u16,
+        /// The low time of a one bit in nanoseconds
+        tl: u16,
+    },
+}
+impl Timing {
+    /// The size of a serial timing profile
+    pub const SERIAL_LEN: usize = 7;
+    /// The serial profile code of a custom timing
+    const CUSTOM: u8 = 0xFF;
+    /// The maximum amount of PIO clock cycles per bit phase (limited by the delay field of the PIO instructions)
+    pub const MAX_CYCLES: u8 = 16;
+    /// The minimum low time after each frame in microseconds, which latches the frame into the LEDs
+    ///
+    /// # Note
+    /// The default of 300 µs is sufficient for the WS2812B and for newer parts that require more than 280 µs.
+    pub const LATCH_MICROS: u32 = const_int_from_compileenv!("WS2812B_LATCH_US" => u32, default: "300");
+
+    /// Decodes the timing profile from its serial representation
+    ///
+    /// # Important
+    /// Custom timings are only valid if all times are non-zero and the high time of a one bit is greater than the high
+    /// time of a zero bit.
+    pub const fn from_serial(data: [u8; Self::SERIAL_LEN]) -> Option<Self> {
+        let [profile, t0h0, t0h1, t1h0, t1h1, tl0, tl1] = data;
+        let (t0h, t1h, tl) =
+            (u16::from_be_bytes([t0h0, t0h1]), u16::from_be_bytes([t1h0, t1h1]), u16::from_be_bytes([tl0, tl1]));
+        match profile {
+            0 => Some(Self::Ws2812b),
+            1 => Some(Self::Ws2811),
+            2 => Some(Self::Sk6812),
+            3 => Some(Self::Ws2813),
+            Self::CUSTOM if t0h > 0 && t1h > t0h && tl > 0 => Some(Self::Custom { t0h, t1h, tl }),
+            _ => None,
+        }
+    }
+
+    /// Encodes the timing profile into its serial representation
+    pub const fn to_serial(self) -> [u8; Self::SERIAL_LEN] {
+        let profile = match self {
+            Self::Ws2812b => 0,
+            Self::Ws2811 => 1,
+            Self::Sk6812 => 2,
+            Self::Ws2813 => 3,
+            Self::Custom { .. } => Self::CUSTOM,
+        };
+        let (t0h, t1h, tl) = match self {
+            Self::Custom { t0h, t1h, tl } => (t0h.to_be_bytes(), t1h.to_be_bytes(), tl.to_be_bytes()),
+            _ => ([0; 2], [0; 2], [0; 2]),
+        };
+        [profile, t0h[0], t0h[1], t1h[0], t1h[1], tl[0], tl[1]]
+    }
+
+    /// The high time of a zero bit, the high time of a one bit and the low time of a one bit in nanoseconds
+    ///
+    /// # Note
+    /// The low time of a zero bit is implied, since both bits have the same period.
+    pub const fn durations(self) -> [u16; 3] {
+        match self {
+            // Note: These are the timings of the original fixed PIO program with 10 cycles per bit at 8 MHz
+            Self::Ws2812b => [250, 875, 375],
+            Self::Ws2811 => [500, 1200, 1300],
+            Self::Sk6812 => [300, 600, 650],
+            Self::Ws2813 => [300, 750, 300],
+            Self::Custom { t0h, t1h, tl } => [t0h, t1h, tl],
+        }
+    }
+
+    /// Derives the PIO clock divider and the cycle counts of the bit phases from the given system clock frequency
+    ///
+    /// # Note
+    /// The divider is chosen as small as possible so that the longest bit phase still fits into `MAX_CYCLES` cycles,
+    /// which gives the best possible resolution. Each bit phase is rounded to the nearest amount of cycles.
+    pub const fn pio_timing(self, clock_frequency: u32) -> PioTiming {
+        /// Nanoseconds per second
+        const NANOS: u64 = 1_000_000_000;
+
+        // Compute the bit phases: the leading low phase, the zero bit high phase and the one bit extra high phase
+        let [t0h, t1h, tl] = self.durations();
+        let phases = [tl as u64, t0h as u64, t1h.saturating_sub(t0h) as u64];
+        let mut longest = phases[0];
+        if phases[1] > longest {
+            longest = phases[1];
+        }
+        if phases[2] > longest {
+            longest = phases[2];
+        }
+
+        // Compute the divider as 16.8 fixed-point number, where 65536.0 is the largest possible divider
+        let clock_frequency = clock_frequency as u64;
+        let divider = (clock_frequency * longest * 256).div_ceil(Self::MAX_CYCLES as u64 * NANOS);
+        let divider = match divider {
+            ..256 => 256,
+            0x100_0001.. => 0x100_0000,
+            divider => divider,
+        };
+
+        // Round each phase to the nearest amount of cycles
+        let mut cycles = [0; 3];
+        let mut index = 0;
+        while index < phases.len() {
+            let phase = ((phases[index] * clock_frequency * 256) + ((divider * NANOS) / 2)) / (divider * NANOS);
+            cycles[index] = match phase {
+                0 => 1,
+                phase if phase > Self::MAX_CYCLES as u64 => Self::MAX_CYCLES,
+                phase => phase as u8,
+            };
+            index += 1;
+        }
+
+        // Note: 65536.0 is represented as 0 in the PIO's clock divider
+        PioTiming { divider_int: (divider >> 8) as u16, divider_frac: divider as u8, cycles }
+    }
+}
+
+/// The PIO clock divider and the cycle counts of the bit phases for a timing profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PioTiming {
+    /// The integer part of the PIO clock divider
+    pub divider_int: u16,
+    /// The fractional part of the PIO clock divider in 1/256
+    pub divider_frac: u8,
+    /// The cycle counts of the leading low phase, the high phase of a zero bit, and the additional high phase of a one
+    /// bit
+    pub cycles: [u8; 3],
+}
+impl PioTiming {
+    /// The minimum time in microseconds to shift out the given amount of bits with the given system clock frequency
+    pub const fn frame_micros(&self, bits: usize, clock_frequency: u32) -> u64 {
+        // Note: 65536.0 is represented as 0 in the PIO's clock divider
+        let divider_int = match self.divider_int {
+            0 => 65536,
+            divider_int => divider_int as u64,
+        };
+        let divider = (divider_int << 8) | (self.divider_frac as u64);
+
+        // Compute the duration
+        let [low, high, extra] = self.cycles;
+        let cycles = bits as u64 * (low as u64 + high as u64 + extra as u64);
+        (cycles * divider * 1_000_000).div_ceil(clock_frequency as u64 * 256)
+    }
+}
+
+/// The configuration of a single LED strip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StripConfig {
+    /// The amount of pixels
+    pub len: usize,
+    /// Whether the strip has a dedicated white channel (e.g. SK6812 RGBW) and expects 32 bit per pixel
+    pub rgbw: bool,
+    /// The order of the color channels
+    pub order: ColorOrder,
+    /// The chipset timing
+    pub timing: Timing,
+    /// Whether the strip is enabled
+    pub enabled: bool,
+}
+impl StripConfig {
+    /// The amount of LED strips (four per PIO block)
+    pub const STRIPS: usize = match cfg!(feature = "pio1") {
+        true => 8,
+        false => 4,
+    };
+
+    /// The strip configurations from the compile-time environment
+    pub const COMPILE_ENV: [Self; Self::STRIPS] = {
+        /// The amount of pixels of strip 0
+        const STRIP0_LEN: usize = const_int_from_compileenv!("WS2812B_STRIP0_LEN" => usize, default: "512");
+        /// The amount of pixels of strip 1
+        const STRIP1_LEN: usize = const_int_from_compileenv!("WS2812B_STRIP1_LEN" => usize, default: "512");
+        /// The amount of pixels of strip 2
+        const STRIP2_LEN: usize = const_int_from_compileenv!("WS2812B_STRIP2_LEN" => usize, default: "512");
+        /// The amount of pixels of strip 3
+        const STRIP3_LEN: usize = const_int_from_compileenv!("WS2812B_STRIP3_LEN" => usize, default: "512");
+        /// The amount of pixels of strip 4
+        const STRIP4_LEN: usize = const_int_from_compileenv!("WS2812B_STRIP4_LEN" => usize, default: "512");
+        /// The amount of pixels of strip 5
+        const STRIP5_LEN: usize = const_int_from_compileenv!("WS2812B_STRIP5_LEN" => usize, default: "512");
+        /// The amount of pixels of strip 6
+        const STRIP6_LEN: usize = const_int_from_compileenv!("WS2812B_STRIP6_LEN" => usize, default: "512");
+        /// The amount of pixels of strip 7
+        const STRIP7_LEN: usize = const_int_from_compileenv!("WS2812B_STRIP7_LEN" => usize, default: "512");
+        /// Whether strip 0 is an RGBW strip
+        const STRIP0_RGBW: u8 = const_int_from_compileenv!("WS2812B_STRIP0_RGBW" => u8, default: "0");
+        /// Whether strip 1 is an RGBW strip
+        const STRIP1_RGBW: u8 = const_int_from_compileenv!("WS2812B_STRIP1_RGBW" => u8, default: "0");
+        /// Whether strip 2 is an RGBW strip
+        const STRIP2_RGBW: u8 = const_int_from_compileenv!("WS2812B_STRIP2_RGBW" => u8, default: "0");
+        /// Whether strip 3 is an RGBW strip
+        const STRIP3_RGBW: u8 = const_int_from_compileenv!("WS2812B_STRIP3_RGBW" => u8, default: "0");
+        /// Whether strip 4 is an RGBW strip
+        const STRIP4_RGBW: u8 = const_int_from_compileenv!("WS2812B_STRIP4_RGBW" => u8, default: "0");
+        /// Whether strip 5 is an RGBW strip
+        const STRIP5_RGBW: u8 = const_int_from_compileenv!("WS2812B_STRIP5_RGBW" => u8, default: "0");
+        /// Whether strip 6 is an RGBW strip
+        const STRIP6_RGBW: u8 = const_int_from_compileenv!("WS2812B_STRIP6_RGBW" => u8, default: "0");
+        /// Whether strip 7 is an RGBW strip
+        const STRIP7_RGBW: u8 = const_int_from_compileenv!("WS2812B_STRIP7_RGBW" => u8, default: "0");
+        /// The color order of strip 0
+        const STRIP0_ORDER: u8 = const_int_from_compileenv!("WS2812B_STRIP0_ORDER" => u8, default: "0");
+        /// The color order of strip 1
+        const STRIP1_ORDER: u8 = const_int_from_compileenv!("WS2812B_STRIP1_ORDER" => u8, default: "0");
+        /// The color order of strip 2
+        const STRIP2_ORDER: u8 = const_int_from_compileenv!("WS2812B_STRIP2_ORDER" => u8, default: "0");
+        /// The color order of strip 3
+        const STRIP3_ORDER: u8 = const_int_from_compileenv!("WS2812B_STRIP3_ORDER" => u8, default: "0");
+        /// The color order of strip 4
+        const STRIP4_ORDER: u8 = const_int_from_compileenv!("WS2812B_STRIP4_ORDER" => u8, default: "0");
+        /// The color order of strip 5
+        const STRIP5_ORDER: u8 = const_int_from_compileenv!("WS2812B_STRIP5_ORDER" => u8, default: "0");
+        /// The color order of strip 6
+        const STRIP6_ORDER: u8 = const_int_from_compileenv!("WS2812B_STRIP6_ORDER" => u8, default: "0");
+        /// The color order of strip 7
+        const STRIP7_ORDER: u8 = const_int_from_compileenv!("WS2812B_STRIP7_ORDER" => u8, default: "0");
+        /// The timing profile of strip 0
+        const STRIP0_TIMING: u8 = const_int_from_compileenv!("WS2812B_STRIP0_TIMING" => u8, default: "0");
+        /// The timing profile of strip 1
+        const STRIP1_TIMING: u8 = const_int_from_compileenv!("WS2812B_STRIP1_TIMING" => u8, default: "0");
+        /// The timing profile of strip 2
+        const STRIP2_TIMING: u8 = const_int_from_compileenv!("WS2812B_STRIP2_TIMING" => u8, default: "0");
+        /// The timing profile of strip 3
+        const STRIP3_TIMING: u8 = const_int_from_compileenv!("WS2812B_STRIP3_TIMING" => u8, default: "0");
+        /// The timing profile of strip 4
+        const STRIP4_TIMING: u8 = const_int_from_compileenv!("WS2812B_STRIP4_TIMING" => u8, default: "0");
+        /// The timing profile of strip 5
+        const STRIP5_TIMING: u8 = const_int_from_compileenv!("WS2812B_STRIP5_TIMING" => u8, default: "0");
+        /// The timing profile of strip 6
+        const STRIP6_TIMING: u8 = const_int_from_compileenv!("WS2812B_STRIP6_TIMING" => u8, default: "0");
+        /// The timing profile of strip 7
+        const STRIP7_TIMING: u8 = const_int_from_compileenv!("WS2812B_STRIP7_TIMING" => u8, default: "0");
+
+        /// Creates a configuration from the compile-time values
+        const fn config(len: usize, rgbw: u8, order: u8, timing: u8) -> StripConfig {
+            let Some(order) = ColorOrder::from_u8(order) else {
+                panic!("invalid color order");
+            };
+            let Some(timing) = Timing::from_serial([timing, 0, 0, 0, 0, 0, 0]) else {
+                panic!("invalid timing profile");
+            };
+            StripConfig { order, timing, ..StripConfig::new(len, rgbw != 0) }
+        }
+
+        // Collect all configurations
+        let all = [
+            config(STRIP0_LEN, STRIP0_RGBW, STRIP0_ORDER, STRIP0_TIMING),
+            config(STRIP1_LEN, STRIP1_RGBW, STRIP1_ORDER, STRIP1_TIMING),
+            config(STRIP2_LEN, STRIP2_RGBW, STRIP2_ORDER, STRIP2_TIMING),
+            config(STRIP3_LEN, STRIP3_RGBW, STRIP3_ORDER, STRIP3_TIMING),
+            config(STRIP4_LEN, STRIP4_RGBW, STRIP4_ORDER, STRIP4_TIMING),
+            config(STRIP5_LEN, STRIP5_RGBW, STRIP5_ORDER, STRIP5_TIMING),
+            config(STRIP6_LEN, STRIP6_RGBW, STRIP6_ORDER, STRIP6_TIMING),
+            config(STRIP7_LEN, STRIP7_RGBW, STRIP7_ORDER, STRIP7_TIMING),
+        ];
+
+        // Take the configurations of the available strips
+        let mut configs = [Self::new(0, false); Self::STRIPS];
+        let mut index = 0;
+        while index < Self::STRIPS {
+            configs[index] = all[index];
+            index += 1;
+        }
+        configs
+    };
+    /// The total amount of pixels of all strips from the compile-time environment
+    ///
+    /// # Note
+    /// This is the size of the pixel pool that is shared by all strips, so the strip lengths can be changed at runtime as
+    /// long as the total amount of pixels does not exceed this value.
+    pub const COMPILE_ENV_PIXELS: usize = Self::pixels(&Self::COMPILE_ENV);
+
+    /// Creates a new enabled WS2812B strip configuration with the given length
+    pub const fn new(len: usize, rgbw: bool) -> Self {
+        Self { len, rgbw, order: ColorOrder::Grb, timing: Timing::Ws2812b, enabled: true }
+    }
+
+    /// The total amount of pixels of all strips
+    pub const fn pixels(configs: &[Self]) -> usize {
+        let mut pixels = 0;
+        let mut index = 0;
+        while index < configs.len() {
+            pixels += configs[index].len;
+            index += 1;
+        }
+        pixels
+    }
+
+    /// Whether all strips fit into the pixel pool
+    pub const fn fits_pool(configs: &[Self]) -> bool {
+        Self::pixels(configs) <= Self::COMPILE_ENV_PIXELS
+    }
+
+    /// Replaces the configuration of the given strip if the strip exists and all strips still fit into the pixel pool
+    pub fn reconfigure(configs: &mut [Self], strip: usize, config: Self) -> bool {
+        // Ensure that the strip exists
+        let Some(slot) = configs.get_mut(strip) else {
+            return false;
+        };
+
+        // Replace the configuration and roll back if the strips don't fit into the pool
+        let previous = core::mem::replace(slot, config);
+        if !Self::fits_pool(configs) {
+            configs[strip] = previous;
+            return false;
+        }
+        true
+    }
+
+    /// Maps a linear LED index onto the enabled strips, where the LEDs are distributed over the strips in ascending
+    /// order, and returns the strip and pixel index
+    pub fn locate(configs: &[Self], index: usize) -> Option<(usize, usize)> {
+        let mut offset = 0;
+        for (strip, config) in configs.iter().enumerate().filter(|(_, config)| config.enabled) {
+            if index < offset + config.len {
+                return Some((strip, index - offset));
+            }
+            offset += config.len;
+        }
+        None
+    }
+
+    /// The amount of bits per pixel
+    pub const fn bits_per_pixel(&self) -> u8 {
+        match self.rgbw {
+            true => 32,
+            false => 24,
+        }
+    }
+
+    /// Encodes a 16 bit RGBW value into the left-aligned word that is shifted out by the state machine
+    pub const fn encode(&self, [r, g, b, w]: [u16; 4]) -> u32 {
+        // Narrow the channels to 8 bit
+        let (r, g, b, w) = (Color::narrow(r), Color::narrow(g), Color::narrow(b), Color::narrow(w));
+
+        // Note: The state machine shifts out the most significant byte first
+        let [first, second, third] = match self.order {
+            ColorOrder::Grb => [g, r, b],
+            ColorOrder::Rgb => [r, g, b],
+            ColorOrder::Brg => [b, r, g],
+            ColorOrder::Rbg => [r, b, g],
+            ColorOrder::Gbr => [g, b, r],
+            ColorOrder::Bgr => [b, g, r],
+        };
+        let rgb = ((first as u32) << 24) | ((second as u32) << 16) | ((third as u32) << 8);
+        match self.rgbw {
+            true => rgb | (w as u32),
+            false => rgb,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_orders_are_applied() {
+        // Encode a pixel with distinct channels in every order
+        let rgbw = [0x0101, 0x0202, 0x0303, 0x0404];
+        let orders = [
+            (ColorOrder::Grb, 0x0201_0300),
+            (ColorOrder::Rgb, 0x0102_0300),
+            (ColorOrder::Brg, 0x0301_0200),
+            (ColorOrder::Rbg, 0x0103_0200),
+            (ColorOrder::Gbr, 0x0203_0100),
+            (ColorOrder::Bgr, 0x0302_0100),
+        ];
+        for (order, word) in orders {
+            let config = StripConfig { order, ..StripConfig::new(1, false) };
+            assert_eq!(config.encode(rgbw), word);
+            assert_eq!(ColorOrder::from_u8(order.to_u8()), Some(order));
+
+            // The white channel is always shifted out last
+            let config = StripConfig { rgbw: true, ..config };
+            assert_eq!(config.encode(rgbw), word | 0x04);
+        }
+    }
+
+    #[test]
+    fn leds_are_distributed_over_enabled_strips() {
+        let mut configs = [StripConfig::new(3, false); 4];
+        configs[1].enabled = false;
+        let located: Vec<_> = (0..10).map(|index| StripConfig::locate(&configs, index)).collect();
+        let expected = [(0, 0), (0, 1), (0, 2), (2, 0), (2, 1), (2, 2), (3, 0), (3, 1), (3, 2)];
+        assert_eq!(located[..9], expected.map(Some));
+        assert_eq!(located[9], None);
+    }
+
+    #[test]
+    fn ws2812b_timing_matches_datasheet_at_125mhz() {
+        // The phases are 375 ns, 250 ns and 625 ns with 39.0625 ns per cycle
+        let timing = Timing::Ws2812b.pio_timing(125_000_000);
+        assert_eq!(timing, PioTiming { divider_int: 4, divider_frac: 226, cycles: [10, 6, 16] });
+    }
+
+    #[test]
+    fn frame_duration_matches_bitrate() {
+        // 512 pixels with 24 bit each at 800 kHz
+        let timing = Timing::Ws2812b.pio_timing(125_000_000);
+        assert_eq!(timing.frame_micros(512 * 24, 125_000_000), 15_360);
+
+        // A single bit at 400 kHz is rounded up to the next microsecond
+        let timing = Timing::Ws2811.pio_timing(125_000_000);
+        assert_eq!(timing.frame_micros(1, 125_000_000), 3);
+    }
+
+    #[test]
+    fn timings_are_approximated() {
+        let timings = [
+            Timing::Ws2812b,
+            Timing::Ws2811,
+            Timing::Sk6812,
+            Timing::Ws2813,
+            Timing::Custom { t0h: 350, t1h: 700, tl: 600 },
+            Timing::Custom { t0h: 1, t1h: 2, tl: 1 },
+            Timing::Custom { t0h: 100, t1h: u16::MAX, tl: u16::MAX },
+        ];
+        for clock_frequency in [12_000_000, 48_000_000, 125_000_000, 133_000_000, 200_000_000] {
+            for timing in timings {
+                // Compute the duration of a PIO cycle in picoseconds
+                let PioTiming { divider_int, divider_frac, cycles } = timing.pio_timing(clock_frequency);
+                let divider = ((divider_int as u64) << 8) | (divider_frac as u64);
+                let cycle = (divider * 1_000_000_000_000) / (clock_frequency as u64 * 256);
+
+                // Ensure that each phase is as close as possible to its target duration
+                let [t0h, t1h, tl] = timing.durations();
+                for (target, cycles) in [tl, t0h, t1h - t0h].into_iter().zip(cycles) {
+                    assert!((1..=Timing::MAX_CYCLES).contains(&cycles), "invalid cycle count for {timing:?}");
+                    let error = (target as u64 * 1000).abs_diff(cycles as u64 * cycle);
+                    // Note: Phases that are shorter than a cycle are extended to the minimum of one cycle
+                    let tolerance = match cycles {
+                        1 => cycle,
+                        _ => cycle / 2 + 1,
+                    };
+                    assert!(error <= tolerance, "inaccurate phase for {timing:?} at {clock_frequency} Hz");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn timings_are_lossless() {
+        for timing in
+            [Timing::Ws2812b, Timing::Ws2811, Timing::Sk6812, Timing::Ws2813, Timing::Custom { t0h: 1, t1h: 2, tl: 3 }]
+        {
+            assert_eq!(Timing::from_serial(timing.to_serial()), Some(timing));
+        }
+
+        // Invalid custom timings are rejected
+        for [t0h, t1h, tl] in [[0, 2, 3], [2, 2, 3], [3, 2, 3], [1, 2, 0]] {
+            assert_eq!(Timing::from_serial(Timing::Custom { t0h, t1h, tl }.to_serial()), None);
+        }
+        assert_eq!(Timing::from_serial([4, 0, 0, 0, 0, 0, 0]), None);
+    }
+}
@@ -0,0 +1,123 @@
+//! The temporal dithering
+
+use crate::const_int_from_compileenv;
+
+/// A temporal dithering that alternates between the two adjacent 8 bit levels of a 16 bit channel value
+///
+/// # Note
+/// Over `PHASES` consecutive refreshes, each channel is shown at the upper level in proportion to its fractional part,
+/// which adds 4 bits of effective resolution. The phase of each pixel is offset by its index, so that adjacent pixels
+/// don't toggle in sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dither {
+    /// Whether the dithering is enabled
+    pub enabled: bool,
+    /// The refresh counter
+    frame: usize,
+}
+impl Dither {
+    /// Whether the dithering is enabled by default
+    pub const COMPILE_ENV: bool = const_int_from_compileenv!("WS2812B_DITHER" => u8, default: "0") != 0;
+    /// The amount of refreshes per dithering cycle
+    pub const PHASES: usize = 16;
+    /// The thresholds for each phase in bit-reversed order, so that the upper level is spread evenly over the cycle
+    const THRESHOLDS: [u8; Self::PHASES] = [0, 8, 4, 12, 2, 10, 6, 14, 1, 9, 5, 13, 3, 11, 7, 15];
+    /// The phase offset between two adjacent pixels, which is coprime to `PHASES`
+    const PIXEL_STRIDE: usize = 7;
+
+    /// Creates a new dithering
+    pub const fn new(enabled: bool) -> Self {
+        Self { enabled, frame: 0 }
+    }
+
+    /// Advances to the next refresh
+    pub fn advance(&mut self) {
+        self.frame = (self.frame + 1) % Self::PHASES;
+    }
+
+    /// Quantizes a 16 bit RGBW value of the given pixel to the 8 bit levels of the current refresh
+    ///
+    /// # Note
+    /// The levels are returned as 16 bit values that narrow to the selected 8 bit level. If the dithering is disabled,
+    /// the value is returned unchanged.
+    pub const fn apply(&self, [r, g, b, w]: [u16; 4], pixel: usize) -> [u16; 4] {
+        if !self.enabled {
+            return [r, g, b, w];
+        }
+
+        // Compute the threshold of the pixel for the current refresh
+        let phase = (self.frame + (pixel % Self::PHASES) * Self::PIXEL_STRIDE) % Self::PHASES;
+        let threshold = (((Self::THRESHOLDS[phase] as u32 * 2) + 1) * 65535) / (Self::PHASES as u32 * 2);
+        [
+            Self::quantize(r, threshold),
+            Self::quantize(g, threshold),
+            Self::quantize(b, threshold),
+            Self::quantize(w, threshold),
+        ]
+    }
+
+    /// Quantizes a 16 bit channel value to the lower or upper adjacent 8 bit level depending on the threshold
+    const fn quantize(value: u16, threshold: u32) -> u16 {
+        // Note: The 8 bit levels are the multiples of `65535` within the scaled value
+        let scaled = value as u32 * 255;
+        let (level, fraction) = (scaled / 65535, scaled % 65535);
+        match fraction > threshold {
+            true => (level as u16 + 1) * 257,
+            false => level as u16 * 257,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Color;
+
+    /// Quantizes the value over a full cycle and returns the 8 bit levels
+    fn cycle(value: u16, pixel: usize) -> [u8; Dither::PHASES] {
+        let mut dither = Dither::new(true);
+        core::array::from_fn(|_| {
+            let [r, ..] = dither.apply([value; 4], pixel);
+            dither.advance();
+            Color::narrow(r)
+        })
+    }
+
+    #[test]
+    fn disabled_dithering_is_transparent() {
+        let dither = Dither::new(false);
+        assert_eq!(dither.apply([0x1234, 0x0081, 0xFFFF, 0], 3), [0x1234, 0x0081, 0xFFFF, 0]);
+    }
+
+    #[test]
+    fn exact_levels_are_stable() {
+        for value in 0..=u8::MAX {
+            for pixel in 0..Dither::PHASES {
+                assert_eq!(cycle(value as u16 * 257, pixel), [value; Dither::PHASES]);
+            }
+        }
+    }
+
+    #[test]
+    fn average_matches_value() {
+        for value in (0..=u16::MAX).step_by(61) {
+            for pixel in [0, 1, 5, 511] {
+                let levels = cycle(value, pixel);
+                let (min, max) = (levels.iter().min().unwrap(), levels.iter().max().unwrap());
+                assert!(max - min <= 1, "{value:#06x} does not alternate between adjacent levels");
+
+                // The average must match within one sixteenth of a level
+                let sum: u32 = levels.iter().map(|level| *level as u32).sum();
+                let expected = (value as f64 * 255.0 * Dither::PHASES as f64) / 65535.0;
+                assert!((sum as f64 - expected).abs() <= 1.0, "{value:#06x} averages to {sum} instead of {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn adjacent_pixels_are_out_of_phase() {
+        // A quarter level is shown at the upper level in four phases, which must differ between adjacent pixels
+        let quarter = 0x40;
+        assert_ne!(cycle(quarter, 0), cycle(quarter, 1));
+    }
+}
@@ -0,0 +1,184 @@
+//! The detection of the serial message format
+
+use crate::ambilight::AmbilightHeader;
+use crate::frame::FrameHeader;
+use crate::tpm2::Tpm2Header;
+
+/// The format of a serial message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A length-prefixed binary frame
+    Frame,
+    /// An Adalight or AWA frame
+    Ambilight,
+    /// A TPM2 packet
+    Tpm2,
+    /// A newline-terminated command line
+    Line,
+}
+
+/// Detects the format of the next serial message from its first bytes
+///
+/// # Note
+/// Binary frames and TPM2 packets are detected by their first byte, which is never a valid byte of a command line.
+/// Ambilight frames are only detected once all magic bytes have been received, since command lines may also start with
+/// `A`. After a corrupted frame, all bytes up to the next newline or magic byte are discarded, so that the remainder of
+/// the corrupted frame is not processed as new messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatDetector {
+    /// The received bytes
+    prefix: [u8; AmbilightHeader::MAGIC_LEN],
+    /// The amount of received bytes
+    len: usize,
+    /// Whether garbage is discarded until the stream is in sync again
+    resync: bool,
+}
+impl FormatDetector {
+    /// Creates a new format detector
+    pub const fn new() -> Self {
+        Self { prefix: [0; AmbilightHeader::MAGIC_LEN], len: 0, resync: false }
+    }
+
+    /// Creates a new format detector that discards all bytes up to the next newline or magic byte
+    pub const fn resync() -> Self {
+        Self { prefix: [0; AmbilightHeader::MAGIC_LEN], len: 0, resync: true }
+    }
+
+    /// Appends the next byte and returns the format once it is known
+    pub fn push(&mut self, byte: u8) -> Option<Format> {
+        // Discard garbage up to and including the next newline, or up to the next magic byte
+        if self.resync && self.len == 0 {
+            match byte {
+                b'\n' => {
+                    self.resync = false;
+                    return None;
+                }
+                FrameHeader::MAGIC | Tpm2Header::MAGIC | AmbilightHeader::MAGIC => (),
+                _ => return None,
+            }
+        }
+
+        // Append the byte
+        self.prefix[self.len] = byte;
+        self.len += 1;
+
+        // Detect the format
+        match self.prefix() {
+            [FrameHeader::MAGIC] => Some(Format::Frame),
+            [Tpm2Header::MAGIC] => Some(Format::Tpm2),
+            prefix if AmbilightHeader::is_magic(prefix) => match prefix.len() {
+                AmbilightHeader::MAGIC_LEN => Some(Format::Ambilight),
+                _ => None,
+            },
+            _ if self.resync => {
+                // Discard the partial magic bytes, but retry the last byte since it may start the next message
+                self.len = 0;
+                self.push(byte)
+            }
+            _ => Some(Format::Line),
+        }
+    }
+
+    /// The received bytes, which belong to the detected message
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix[..self.len]
+    }
+}
+impl Default for FormatDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ack::AckMode;
+    use crate::line::LineBuffer;
+
+    /// Pushes the bytes until the format is detected and returns the format and the prefix
+    fn detect(data: &[u8]) -> (Option<Format>, Vec<u8>) {
+        let mut detector = FormatDetector::new();
+        let format = data.iter().find_map(|byte| detector.push(*byte));
+        (format, detector.prefix().to_vec())
+    }
+
+    /// Pushes the bytes after a corrupted frame until the format is detected and returns the format and the remaining
+    /// bytes including the prefix
+    fn resync(data: &[u8]) -> (Option<Format>, Vec<u8>) {
+        let mut detector = FormatDetector::resync();
+        let mut bytes = data.iter();
+        let format = bytes.by_ref().find_map(|byte| detector.push(*byte));
+        let remainder = detector.prefix().iter().chain(bytes).copied().collect();
+        (format, remainder)
+    }
+
+    #[test]
+    fn formats_are_detected() {
+        assert_eq!(detect(&[0xA5, 0x02, 0x00, 0x00]), (Some(Format::Frame), vec![0xA5]));
+        assert_eq!(detect(&[0xC9, 0xDA, 0x00, 0x03]), (Some(Format::Tpm2), vec![0xC9]));
+        assert_eq!(detect(b"Ada\x01\x2B\x7F"), (Some(Format::Ambilight), b"Ada".to_vec()));
+        assert_eq!(detect(b"AwA\x00\x00\x55"), (Some(Format::Ambilight), b"AwA".to_vec()));
+        assert_eq!(detect(b"SHOW\n"), (Some(Format::Line), b"S".to_vec()));
+        assert_eq!(detect(b"Aw\n"), (Some(Format::Line), b"Aw\n".to_vec()));
+        assert_eq!(detect(b"Aw"), (None, b"Aw".to_vec()));
+    }
+
+    #[test]
+    fn ack_commands_are_dispatched_as_lines() {
+        let commands = [
+            (&b"ACK ECHO\n"[..], AckMode::Echo),
+            (b"ACK COMPACT\n", AckMode::Compact),
+            (b"ACK EVERY 16\n", AckMode::Every(16)),
+            (b"ACK SILENT\n", AckMode::Silent),
+        ];
+        for (command, mode) in commands {
+            // Detect the format like the serial task
+            let mut detector = FormatDetector::new();
+            let mut bytes = command.iter();
+            let format = bytes.by_ref().find_map(|byte| detector.push(*byte));
+            assert_eq!(format, Some(Format::Line));
+
+            // Read the command line like the serial task
+            let mut line = LineBuffer::new();
+            let complete = line.extend(detector.prefix()) || bytes.any(|byte| line.push(*byte));
+            assert!(complete);
+            assert_eq!(line.line().and_then(AckMode::from_serial), Some(mode));
+        }
+    }
+
+    #[test]
+    fn garbage_is_discarded_after_corrupted_frames() {
+        // Garbage is discarded including the newline
+        assert_eq!(
+            resync(b"\x12\x34garbage\n0000000fabcdef00\n"),
+            (Some(Format::Line), b"0000000fabcdef00\n".to_vec())
+        );
+        assert_eq!(resync(b"garbage\nSHOW\n"), (Some(Format::Line), b"SHOW\n".to_vec()));
+
+        // Magic bytes start the next message immediately
+        assert_eq!(resync(&[0x01, 0xFF, 0xA5, 0x02, 0x00, 0x00]), (Some(Format::Frame), vec![0xA5, 0x02, 0x00, 0x00]));
+        assert_eq!(resync(b"\x00\xC9\xDA"), (Some(Format::Tpm2), b"\xC9\xDA".to_vec()));
+        assert_eq!(resync(b"xAAwA\x00"), (Some(Format::Ambilight), b"AwA\x00".to_vec()));
+
+        // Partial magic bytes are garbage, too
+        assert_eq!(resync(b"ACK ECHO\n"), (None, Vec::new()));
+        assert_eq!(resync(b"Ad\nSHOW\n"), (Some(Format::Line), b"SHOW\n".to_vec()));
+    }
+
+    #[test]
+    fn stream_recovers_after_corrupted_frame() {
+        // A stray magic byte followed by an unknown frame kind and a huge length
+        let stream = b"\xA5garbage\n0000000fabcdef00\n";
+        assert_eq!(detect(stream), (Some(Format::Frame), vec![0xA5]));
+        let header = FrameHeader::from_serial(&stream[..FrameHeader::SERIAL_LEN]).expect("failed to decode header");
+        assert_eq!(FrameHeader::len_max(header.kind), None);
+
+        // The remainder of the corrupted line is discarded, and the next command line is read in sync
+        let (format, remainder) = resync(&stream[FrameHeader::SERIAL_LEN..]);
+        assert_eq!(format, Some(Format::Line));
+        let mut line = LineBuffer::new();
+        assert!(line.extend(&remainder));
+        assert_eq!(line.line(), Some(&b"0000000fabcdef00\n"[..]));
+    }
+}
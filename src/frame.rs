@@ -0,0 +1,509 @@
+//! A length-prefixed binary frame
+
+use crate::command::{Color, Command, DecodeError};
+use crate::config::{ColorOrder, StripConfig, Timing};
+use crate::gamma::{Curve, Table};
+use crate::message::Message;
+use crate::settings::Settings;
+use crate::universe::{UniverseMap, UniverseMapping};
+
+/// The header of a length-prefixed binary frame
+///
+/// # Format
+/// Each frame starts with a 4 byte header (indices are byte offsets):
+///  - `[0]`: The magic byte `0xA5`
+///  - `[1]`: The frame kind
+///  - `[2, 4)`: The big-endian length of the frame body in bytes
+#[derive(Debug, Clone, Copy)]
+pub struct FrameHeader {
+    /// The frame kind
+    pub kind: u8,
+    /// The length of the frame body
+    pub len: usize,
+}
+impl FrameHeader {
+    /// The magic byte that marks the start of a binary frame
+    pub const MAGIC: u8 = 0xA5;
+    /// The size of a serial frame header
+    pub const SERIAL_LEN: usize = 4;
+
+    /// Decodes a serial frame header
+    pub fn from_serial(data: &[u8]) -> Option<Self> {
+        let [Self::MAGIC, kind, len_high, len_low] = *data else {
+            return None;
+        };
+        Some(Self { kind, len: ((len_high as usize) << 8) | (len_low as usize) })
+    }
+
+    /// Encodes the frame header into its serial representation
+    pub const fn to_serial(self) -> [u8; Self::SERIAL_LEN] {
+        [Self::MAGIC, self.kind, (self.len >> 8) as u8, self.len as u8]
+    }
+
+    /// The maximum body length of the given frame kind, or `None` if the frame kind is unknown
+    ///
+    /// # Note
+    /// A header that exceeds the maximum length of its frame kind is considered corrupted, so that a stray magic byte
+    /// cannot swallow the following frames.
+    pub const fn len_max(kind: u8) -> Option<usize> {
+        match kind {
+            // Note: The length of a pixel batch is validated against the batch header
+            PixelBatch::KIND => Some(u16::MAX as usize),
+            Control::KIND_SHOW => Some(0),
+            Control::KIND_MODE | Control::KIND_CURVE | Control::KIND_DITHER => Some(1),
+            Control::KIND_BRIGHTNESS => Some(2),
+            Control::KIND_POWER_BUDGET => Some(Control::SERIAL_LEN),
+            StripSetup::KIND_SET => Some(StripSetup::SERIAL_LEN),
+            StripSetup::KIND_GET => Some(1),
+            SettingsFrame::KIND_GET | SettingsFrame::KIND_RESET => Some(0),
+            SettingsFrame::KIND_SET => Some(Settings::SERIAL_LEN),
+            GammaUpload::KIND => Some(GammaUpload::SERIAL_LEN),
+            PowerReport::KIND => Some(0),
+            UniverseData::KIND => Some(UniverseData::SERIAL_LEN_MAX),
+            UniverseSetup::KIND_SET => Some(UniverseSetup::SERIAL_LEN),
+            UniverseSetup::KIND_GET => Some(1),
+            AckSetup::KIND => Some(AckSetup::SERIAL_LEN_MAX),
+            FramingReport::KIND => Some(0),
+            _ => None,
+        }
+    }
+}
+
+/// A batch update of a consecutive range of pixels within a single strip
+///
+/// # Format
+/// The frame body consists of a 6 byte batch header (indices are byte offsets), followed by the packed pixel data:
+///  - `[0]`: The index of the LED strip
+///  - `[1]`: The amount of bytes per pixel; must be `3` (RGB), `4` (RGBW), `6` (16 bit RGB) or `8` (16 bit RGBW)
+///  - `[2, 4)`: The big-endian index of the first pixel to update
+///  - `[4, 6)`: The big-endian amount of pixels to update
+///  - `[6, ..)`: The packed RGB(W) values for each pixel, where 16 bit values are big-endian
+#[derive(Debug, Clone, Copy)]
+pub struct PixelBatch {
+    /// The index of the WS2812B strip to update
+    pub strip: usize,
+    /// The amount of bytes per pixel
+    pub channels: usize,
+    /// The index of the first pixel to update
+    pub start: usize,
+    /// The amount of pixels to update
+    pub count: usize,
+}
+impl PixelBatch {
+    /// The frame kind of a pixel batch
+    pub const KIND: u8 = 0x01;
+    /// The size of a serial batch header
+    pub const SERIAL_LEN: usize = 6;
+
+    /// Decodes a serial batch header and validates it against the frame header and the given strip configurations
+    ///
+    /// # Note
+    /// The entire pixel range is validated, and a white channel is only accepted for RGBW strips, so that every pixel
+    /// of a valid batch is a valid update.
+    pub fn from_serial(header: &FrameHeader, data: &[u8], configs: &[StripConfig]) -> Result<Self, DecodeError> {
+        // Validate the frame kind and destructure the batch header
+        let FrameHeader { kind: Self::KIND, len } = *header else {
+            return Err(DecodeError::InvalidFrame);
+        };
+        let [strip, channels @ (3 | 4 | 6 | 8), start_high, start_low, count_high, count_low] = *data else {
+            return Err(DecodeError::InvalidFrame);
+        };
+
+        // Cast the bytes
+        let strip = strip as usize;
+        let channels = channels as usize;
+        let start = ((start_high as usize) << 8) | (start_low as usize);
+        let count = ((count_high as usize) << 8) | (count_low as usize);
+
+        // Validate the frame length
+        if len != Self::SERIAL_LEN + (count * channels) || count == 0 {
+            return Err(DecodeError::InvalidFrame);
+        }
+
+        // Validate the first and the last pixel with the largest white value the batch can carry
+        let white = match channels {
+            4 | 8 => 1,
+            _ => 0,
+        };
+        Command::validate(strip, start, Color::Rgbw8([0, 0, 0, white]), configs)?;
+        Command::validate(strip, start + count - 1, Color::Rgbw8([0, 0, 0, white]), configs)?;
+
+        // Init self
+        Ok(Self { strip, channels, start, count })
+    }
+
+    /// Creates the update command for the pixel at the given offset from its packed RGB(W) value and validates it against
+    /// the given strip configurations
+    pub fn command(&self, offset: usize, pixel: &[u8], configs: &[StripConfig]) -> Option<Command> {
+        // Copy the packed value into an RGBW array
+        let mut rgbw = [0; 8];
+        rgbw[..self.channels].copy_from_slice(pixel.get(..self.channels)?);
+
+        // Decode the color
+        let color = match self.channels {
+            3 | 4 => Color::Rgbw8([rgbw[0], rgbw[1], rgbw[2], rgbw[3]]),
+            _ => Color::Rgbw16([
+                u16::from_be_bytes([rgbw[0], rgbw[1]]),
+                u16::from_be_bytes([rgbw[2], rgbw[3]]),
+                u16::from_be_bytes([rgbw[4], rgbw[5]]),
+                u16::from_be_bytes([rgbw[6], rgbw[7]]),
+            ]),
+        };
+        Command::new(self.strip, self.start + offset, color, configs)
+    }
+}
+
+/// A strip configuration frame
+///
+/// # Format
+/// The frame kind selects whether the configuration is set or queried:
+///  - `0x04`: Sets the configuration of a strip; the body is the 13 byte strip configuration
+///  - `0x05`: Queries the configuration of a strip; the body is a single byte with the index of the LED strip
+///
+/// The strip configuration has the following format (indices are byte offsets):
+///  - `[0]`: The index of the LED strip
+///  - `[1, 4)`: The big-endian amount of pixels
+///  - `[4]`: The flags, where bit `0` marks an RGBW strip and bit `1` enables the strip
+///  - `[5]`: The color order
+///  - `[6, 13)`: The timing profile (see `Timing`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StripSetup {
+    /// The index of the WS2812B strip to configure
+    pub strip: usize,
+    /// The strip configuration
+    pub config: StripConfig,
+}
+impl StripSetup {
+    /// The frame kind to set the configuration of a strip
+    pub const KIND_SET: u8 = 0x04;
+    /// The frame kind to query the configuration of a strip
+    pub const KIND_GET: u8 = 0x05;
+    /// The size of a serial strip configuration
+    pub const SERIAL_LEN: usize = 6 + Timing::SERIAL_LEN;
+
+    /// Decodes a serial strip configuration
+    pub fn from_serial(data: &[u8]) -> Option<Self> {
+        let data: &[u8; Self::SERIAL_LEN] = data.try_into().ok()?;
+        let [strip, len0, len1, len2, flags, order, timing @ ..] = *data;
+
+        // Decode the fields
+        let len = u32::from_be_bytes([0, len0, len1, len2]) as usize;
+        let (rgbw, enabled) = (flags & 0b01 != 0, flags & 0b10 != 0);
+        let (order, timing) = (ColorOrder::from_u8(order)?, Timing::from_serial(timing)?);
+        Some(Self { strip: strip as usize, config: StripConfig { len, rgbw, order, timing, enabled } })
+    }
+
+    /// Encodes the strip configuration into its serial representation
+    pub const fn to_serial(self) -> [u8; Self::SERIAL_LEN] {
+        let StripConfig { len, rgbw, order, timing, enabled } = self.config;
+        let [_, len0, len1, len2] = (len as u32).to_be_bytes();
+        let flags = ((enabled as u8) << 1) | (rgbw as u8);
+        let [t0, t1, t2, t3, t4, t5, t6] = timing.to_serial();
+        [self.strip as u8, len0, len1, len2, flags, order.to_u8(), t0, t1, t2, t3, t4, t5, t6]
+    }
+}
+
+/// A DMX512 universe data frame
+///
+/// # Format
+/// The frame body consists of the big-endian universe, followed by up to 512 channel values (indices are byte offsets):
+///  - `[0, 2)`: The big-endian universe
+///  - `[2, ..)`: The channel values, starting with the first channel of the universe
+#[derive(Debug, Clone, Copy)]
+pub struct UniverseData;
+impl UniverseData {
+    /// The frame kind of a universe data frame
+    pub const KIND: u8 = 0x0F;
+    /// The maximum size of a serial universe data body
+    pub const SERIAL_LEN_MAX: usize = 2 + UniverseMap::CHANNELS;
+
+    /// Decodes a serial universe data body into the universe and the channel values
+    pub fn from_serial(data: &[u8]) -> Option<(u16, &[u8])> {
+        let [universe_high, universe_low, channels @ ..] = data else {
+            return None;
+        };
+        if channels.len() > UniverseMap::CHANNELS {
+            return None;
+        }
+        Some((u16::from_be_bytes([*universe_high, *universe_low]), channels))
+    }
+}
+
+/// A universe mapping frame
+///
+/// # Format
+/// The frame kind selects whether the mapping is set or queried:
+///  - `0x10`: Sets the mapping of a slot; the body is the 11 byte mapping
+///  - `0x11`: Queries the mapping of a slot; the body is a single byte with the index of the slot
+///
+/// The mapping has the following format (indices are byte offsets):
+///  - `[0]`: The index of the mapping slot
+///  - `[1, 3)`: The big-endian universe
+///  - `[3, 5)`: The big-endian first channel within the universe (zero-based)
+///  - `[5]`: The index of the LED strip
+///  - `[6, 9)`: The big-endian index of the first pixel within the strip
+///  - `[9, 11)`: The big-endian amount of pixels, or `0` to clear the slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniverseSetup {
+    /// The index of the mapping slot
+    pub slot: usize,
+    /// The mapping, or `None` if the slot is cleared
+    pub mapping: Option<UniverseMapping>,
+}
+impl UniverseSetup {
+    /// The frame kind to set the mapping of a slot
+    pub const KIND_SET: u8 = 0x10;
+    /// The frame kind to query the mapping of a slot
+    pub const KIND_GET: u8 = 0x11;
+    /// The size of a serial universe mapping
+    pub const SERIAL_LEN: usize = 11;
+
+    /// Decodes a serial universe mapping
+    pub fn from_serial(data: &[u8]) -> Option<Self> {
+        let data: &[u8; Self::SERIAL_LEN] = data.try_into().ok()?;
+        let [slot, universe0, universe1, channel0, channel1, strip, pixel0, pixel1, pixel2, count0, count1] = *data;
+
+        // Decode the fields
+        let (slot, strip) = (slot as usize, strip as usize);
+        let (universe, channel) =
+            (u16::from_be_bytes([universe0, universe1]), u16::from_be_bytes([channel0, channel1]));
+        let pixel = u32::from_be_bytes([0, pixel0, pixel1, pixel2]) as usize;
+        let count = u16::from_be_bytes([count0, count1]) as usize;
+
+        // Validate the fields
+        if slot >= UniverseMap::SLOTS || channel as usize >= UniverseMap::CHANNELS || strip >= StripConfig::STRIPS {
+            return None;
+        }
+        let mapping = match count {
+            0 => None,
+            count => Some(UniverseMapping { universe, channel, strip, pixel, count }),
+        };
+        Some(Self { slot, mapping })
+    }
+
+    /// Encodes the universe mapping into its serial representation
+    pub const fn to_serial(self) -> [u8; Self::SERIAL_LEN] {
+        let UniverseMapping { universe, channel, strip, pixel, count } = match self.mapping {
+            Some(mapping) => mapping,
+            None => UniverseMapping { universe: 0, channel: 0, strip: 0, pixel: 0, count: 0 },
+        };
+        let ([universe0, universe1], [channel0, channel1]) = (universe.to_be_bytes(), channel.to_be_bytes());
+        let [_, pixel0, pixel1, pixel2] = (pixel as u32).to_be_bytes();
+        let [count0, count1] = (count as u16).to_be_bytes();
+        [self.slot as u8, universe0, universe1, channel0, channel1, strip as u8, pixel0, pixel1, pixel2, count0, count1]
+    }
+}
+
+/// A persistent settings frame
+///
+/// # Format
+/// The frame kind selects the operation on the settings store:
+///  - `0x06`: Queries the persistent settings; the body must be empty, and the driver replies with a write frame that
+///    contains the current settings
+///  - `0x07`: Writes the persistent settings; the body is the 123 byte serialized settings (see `Settings`)
+///  - `0x08`: Resets the persistent settings to the compile-time defaults; the body must be empty
+///
+/// New settings take effect after the next reboot.
+#[derive(Debug, Clone, Copy)]
+pub struct SettingsFrame;
+impl SettingsFrame {
+    /// The frame kind to query the persistent settings
+    pub const KIND_GET: u8 = 0x06;
+    /// The frame kind to write the persistent settings
+    pub const KIND_SET: u8 = 0x07;
+    /// The frame kind to reset the persistent settings
+    pub const KIND_RESET: u8 = 0x08;
+}
+
+/// A custom gamma table upload frame
+///
+/// # Format
+/// The frame body consists of 256 big-endian 16 bit table entries, where the entry at index `i` is the duty cycle for the
+/// 8 bit channel value `i`. The uploaded table is selected as gamma curve once all entries are transferred.
+#[derive(Debug, Clone, Copy)]
+pub struct GammaUpload;
+impl GammaUpload {
+    /// The frame kind to upload a custom gamma table
+    pub const KIND: u8 = 0x0B;
+    /// The size of a serial gamma table
+    pub const SERIAL_LEN: usize = size_of::<Table>();
+
+    /// Decodes the table entry with the given index into the associated inter-core message
+    pub fn entry(index: usize, data: &[u8]) -> Option<Message> {
+        let index = u8::try_from(index).ok()?;
+        let &[high, low] = data else {
+            return None;
+        };
+        Some(Message::SetGammaEntry(index, u16::from_be_bytes([high, low])))
+    }
+}
+
+/// An acknowledgement mode frame
+///
+/// # Format
+/// The frame body selects the acknowledgement mode (see `AckMode::from_frame`). The frame itself is acknowledged with
+/// the previous mode, and the new mode applies to all subsequent commands.
+#[derive(Debug, Clone, Copy)]
+pub struct AckSetup;
+impl AckSetup {
+    /// The frame kind to select the acknowledgement mode
+    pub const KIND: u8 = 0x12;
+    /// The maximum size of a serial acknowledgement mode body
+    pub const SERIAL_LEN_MAX: usize = 3;
+}
+
+/// A framing error report frame
+///
+/// # Format
+/// The query has an empty body, and the driver replies with a frame of the same kind that has a fixed size of 8 bytes
+/// (indices are byte offsets):
+///  - `[0, 4)`: The big-endian amount of discarded command lines that exceeded the line buffer
+///  - `[4, 8)`: The big-endian amount of rejected binary frames, and of ambilight or TPM2 frames with an invalid
+///    header, checksum or end byte
+///
+/// Both counters saturate and are reset during boot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FramingReport {
+    /// The amount of discarded overlong command lines
+    pub overlong: u32,
+    /// The amount of rejected or corrupted frames
+    pub malformed: u32,
+}
+impl FramingReport {
+    /// The frame kind to query the framing error report
+    pub const KIND: u8 = 0x13;
+    /// The size of a serial framing error report
+    pub const SERIAL_LEN: usize = 8;
+
+    /// Encodes the framing error report into its serial representation
+    pub fn to_serial(&self) -> [u8; Self::SERIAL_LEN] {
+        let mut data = [0; Self::SERIAL_LEN];
+        data[..4].copy_from_slice(&self.overlong.to_be_bytes());
+        data[4..].copy_from_slice(&self.malformed.to_be_bytes());
+        data
+    }
+}
+
+/// A power report frame
+///
+/// # Format
+/// The query has an empty body, and the driver replies with a frame of the same kind that has a fixed size of 36 bytes
+/// (indices are byte offsets):
+///  - `[0, 4)`: The big-endian estimated current of all strips in mA
+///  - `[4, 36)`: The big-endian estimated currents of eight strips in mA, where strips that are not available draw `0`
+///
+/// The estimates are taken from the last refresh, after the power budget has been applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerReport {
+    /// The estimated current of each strip in mA
+    pub strips: [u32; StripConfig::STRIPS],
+}
+impl PowerReport {
+    /// The frame kind to query the power report
+    pub const KIND: u8 = 0x0D;
+    /// The size of a serial power report
+    pub const SERIAL_LEN: usize = 36;
+
+    /// Encodes the power report into its serial representation
+    pub fn to_serial(&self) -> [u8; Self::SERIAL_LEN] {
+        let mut data = [0; Self::SERIAL_LEN];
+        let total = self.strips.iter().fold(0u32, |total, current| total.saturating_add(*current));
+        data[..4].copy_from_slice(&total.to_be_bytes());
+        for (chunk, current) in data[4..].chunks_exact_mut(4).zip(self.strips) {
+            chunk.copy_from_slice(&current.to_be_bytes());
+        }
+        data
+    }
+}
+
+/// A control frame
+///
+/// # Format
+/// The frame kind selects the control message, and the frame body contains its argument (if any):
+///  - `0x02`: Latches all pending updates and refreshes all strips; the body must be empty
+///  - `0x03`: Sets the update mode; the body is a single byte, either `0` (latched) or `1` (immediate)
+///  - `0x09`: Sets the brightness; the body is the index of the LED strip, or `255` for the global brightness, followed by
+///    the brightness from `0` (off) to `255` (full)
+///  - `0x0A`: Selects the gamma curve; the body is a single byte with the curve code (see `Curve`)
+///  - `0x0C`: Sets the power budget; the body is the index of the LED strip, or `255` for the total budget, followed by
+///    the big-endian 32 bit budget in mA, where `0` disables the limit
+///  - `0x0E`: Sets the temporal dithering; the body is a single byte, either `0` (disabled) or `1` (enabled)
+#[derive(Debug, Clone, Copy)]
+pub struct Control;
+impl Control {
+    /// The frame kind to latch all pending updates
+    pub const KIND_SHOW: u8 = 0x02;
+    /// The frame kind to set the update mode
+    pub const KIND_MODE: u8 = 0x03;
+    /// The frame kind to set the brightness
+    pub const KIND_BRIGHTNESS: u8 = 0x09;
+    /// The frame kind to select the gamma curve
+    pub const KIND_CURVE: u8 = 0x0A;
+    /// The frame kind to set the power budget
+    pub const KIND_POWER_BUDGET: u8 = 0x0C;
+    /// The frame kind to set the temporal dithering
+    pub const KIND_DITHER: u8 = 0x0E;
+    /// The strip index that selects the global brightness or the total power budget
+    pub const GLOBAL: u8 = 0xFF;
+    /// The maximum size of a serial control body
+    pub const SERIAL_LEN: usize = 5;
+
+    /// Decodes a control frame body into the associated inter-core message
+    pub fn from_serial(header: &FrameHeader, data: &[u8]) -> Option<Message> {
+        match (header.kind, data) {
+            (Self::KIND_SHOW, []) => Some(Message::Show),
+            (Self::KIND_MODE, [0]) => Some(Message::SetImmediate(false)),
+            (Self::KIND_MODE, [1]) => Some(Message::SetImmediate(true)),
+            (Self::KIND_BRIGHTNESS, [Self::GLOBAL, level]) => Some(Message::SetBrightness(None, *level)),
+            (Self::KIND_BRIGHTNESS, [strip, level]) if (*strip as usize) < StripConfig::STRIPS => {
+                Some(Message::SetBrightness(Some(*strip as usize), *level))
+            }
+            (Self::KIND_DITHER, [0]) => Some(Message::SetDither(false)),
+            (Self::KIND_DITHER, [1]) => Some(Message::SetDither(true)),
+            (Self::KIND_CURVE, [curve]) => Some(Message::SetCurve(Curve::from_u8(*curve)?)),
+            (Self::KIND_POWER_BUDGET, [Self::GLOBAL, budget @ ..]) => {
+                Some(Message::SetPowerBudget(None, u32::from_be_bytes(budget.try_into().ok()?)))
+            }
+            (Self::KIND_POWER_BUDGET, [strip, budget @ ..]) if (*strip as usize) < StripConfig::STRIPS => {
+                Some(Message::SetPowerBudget(Some(*strip as usize), u32::from_be_bytes(budget.try_into().ok()?)))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lengths_are_bounded_by_frame_kind() {
+        assert_eq!(FrameHeader::len_max(Control::KIND_SHOW), Some(0));
+        assert_eq!(FrameHeader::len_max(Control::KIND_POWER_BUDGET), Some(5));
+        assert_eq!(FrameHeader::len_max(GammaUpload::KIND), Some(512));
+        assert_eq!(FrameHeader::len_max(UniverseData::KIND), Some(514));
+        assert_eq!(FrameHeader::len_max(0x00), None);
+        assert_eq!(FrameHeader::len_max(0xFF), None);
+    }
+
+    #[test]
+    fn batches_are_validated_entirely() {
+        let configs = [StripConfig::new(16, false), StripConfig::new(16, true)];
+        let header = |len| FrameHeader { kind: PixelBatch::KIND, len };
+        let decode =
+            |len, data: [u8; 6]| PixelBatch::from_serial(&header(len), &data, &configs).map(|batch| batch.count);
+
+        assert_eq!(decode(6 + 16 * 3, [0, 3, 0, 0, 0, 16]), Ok(16));
+        assert_eq!(decode(6 + 2 * 8, [1, 8, 0, 14, 0, 2]), Ok(2));
+
+        // Malformed batch headers
+        assert_eq!(decode(6 + 2 * 3, [0, 5, 0, 0, 0, 2]), Err(DecodeError::InvalidFrame));
+        assert_eq!(decode(6 + 2 * 3, [0, 3, 0, 0, 0, 3]), Err(DecodeError::InvalidFrame));
+        assert_eq!(decode(6, [0, 3, 0, 0, 0, 0]), Err(DecodeError::InvalidFrame));
+
+        // Invalid strips, pixel ranges or white channels
+        assert_eq!(decode(6 + 3, [2, 3, 0, 0, 0, 1]), Err(DecodeError::InvalidStrip));
+        assert_eq!(decode(6 + 2 * 3, [0, 3, 0, 15, 0, 2]), Err(DecodeError::InvalidPixel));
+        assert_eq!(decode(6 + 4, [0, 4, 0, 0, 0, 1]), Err(DecodeError::InvalidWhite));
+    }
+}
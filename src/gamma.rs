@@ -0,0 +1,233 @@
+//! The gamma correction curves
+
+use crate::const_int_from_compileenv;
+
+/// The curve that maps the color channels to the LED duty cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curve {
+    /// No correction
+    Linear,
+    /// A power curve with gamma 2.2
+    Gamma22,
+    /// A power curve with gamma 2.8
+    Gamma28,
+    /// The CIE 1931 lightness curve
+    Cie,
+    /// The uploaded custom table
+    Custom,
+}
+impl Curve {
+    /// The serial curve code of the custom table
+    const CUSTOM: u8 = 0xFF;
+
+    /// Decodes the curve from its serial representation
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Linear),
+            1 => Some(Self::Gamma22),
+            2 => Some(Self::Gamma28),
+            3 => Some(Self::Cie),
+            Self::CUSTOM => Some(Self::Custom),
+            _ => None,
+        }
+    }
+
+    /// Encodes the curve into its serial representation
+    pub const fn to_u8(self) -> u8 {
+        match self {
+            Self::Linear => 0,
+            Self::Gamma22 => 1,
+            Self::Gamma28 => 2,
+            Self::Cie => 3,
+            Self::Custom => Self::CUSTOM,
+        }
+    }
+}
+
+/// A lookup table that maps each 8 bit channel value to a 16 bit duty cycle
+pub type Table = [u16; 256];
+
+/// The gamma correction that is applied per channel before the pixels are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gamma {
+    /// The selected curve
+    pub curve: Curve,
+    /// The uploaded custom table
+    pub custom: Table,
+}
+impl Gamma {
+    /// The curve from the compile-time environment
+    pub const COMPILE_ENV: Curve = {
+        /// The curve code
+        const CURVE: u8 = const_int_from_compileenv!("WS2812B_GAMMA" => u8, default: "0");
+        crate::const_unwrap!(Curve::from_u8(CURVE), "invalid gamma curve")
+    };
+
+    /// The identity table
+    pub const LINEAR: Table = Self::table(Curve::Linear);
+    /// The power curve with gamma 2.2
+    pub const GAMMA22: Table = Self::table(Curve::Gamma22);
+    /// The power curve with gamma 2.8
+    pub const GAMMA28: Table = Self::table(Curve::Gamma28);
+    /// The CIE 1931 lightness curve
+    pub const CIE: Table = Self::table(Curve::Cie);
+
+    /// Creates a new gamma correction with the given curve, where the custom table is initialized as linear table
+    pub const fn new(curve: Curve) -> Self {
+        Self { curve, custom: Self::LINEAR }
+    }
+
+    /// The lookup table of the selected curve
+    pub const fn lookup_table(&self) -> &Table {
+        match self.curve {
+            Curve::Linear => &Self::LINEAR,
+            Curve::Gamma22 => &Self::GAMMA22,
+            Curve::Gamma28 => &Self::GAMMA28,
+            Curve::Cie => &Self::CIE,
+            Curve::Custom => &self.custom,
+        }
+    }
+
+    /// Applies the selected curve to a 16 bit RGBW value
+    pub const fn apply(&self, [r, g, b, w]: [u16; 4]) -> [u16; 4] {
+        let table = self.lookup_table();
+        [Self::lookup(table, r), Self::lookup(table, g), Self::lookup(table, b), Self::lookup(table, w)]
+    }
+
+    /// Maps a 16 bit channel value via the given table
+    ///
+    /// # Note
+    /// Values between two table entries are interpolated linearly, so that 16 bit colors are not truncated to 8 bit
+    /// before the correction. 8 bit values (i.e. multiples of `257`) map exactly to their table entry.
+    pub const fn lookup(table: &Table, value: u16) -> u16 {
+        let position = value as u32 * 255;
+        let (index, fraction) = ((position / 65535) as usize, position % 65535);
+        let (low, high) = match index {
+            255 => (table[255], table[255]),
+            index => (table[index], table[index + 1]),
+        };
+
+        // Note: The interpolation is signed since custom tables are not necessarily monotonic
+        let delta = (high as i64 - low as i64) * fraction as i64;
+        (low as i64 + (delta / 65535)) as u16
+    }
+
+    /// Computes the table of a built-in curve, where the custom curve is computed as linear table
+    const fn table(curve: Curve) -> Table {
+        let mut table = [0; 256];
+        let mut index = 0;
+        while index < table.len() {
+            // Map the value within `[0, 1]`
+            let x = index as f64 / 255.0;
+            let y = match curve {
+                Curve::Linear | Curve::Custom => x,
+                Curve::Gamma22 => powf(x, 2.2),
+                Curve::Gamma28 => powf(x, 2.8),
+                Curve::Cie if x <= 0.08 => (x * 100.0) / 903.3,
+                Curve::Cie => {
+                    let base = ((x * 100.0) + 16.0) / 116.0;
+                    base * base * base
+                }
+            };
+            table[index] = ((y * 65535.0) + 0.5) as u16;
+            index += 1;
+        }
+        table
+    }
+}
+
+/// Computes `x^y` for `x` within `[0, 1]` and positive `y`
+///
+/// # Note
+/// This is a compile-time replacement for `f64::powf`, which is not available in `core`.
+const fn powf(x: f64, y: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+
+    // Split `x` into `mantissa * 2^exponent` with the mantissa within `[1, 2)`
+    let (mut mantissa, mut exponent) = (x, 0.0);
+    while mantissa < 1.0 {
+        mantissa *= 2.0;
+        exponent -= 1.0;
+    }
+
+    // Compute `ln(mantissa)` via the `atanh` series, which converges quickly since `z` is within `[0, 1/3)`
+    let z = (mantissa - 1.0) / (mantissa + 1.0);
+    let (mut ln, mut power, mut n) = (0.0, z, 1.0);
+    while n < 64.0 {
+        ln += power / n;
+        power *= z * z;
+        n += 2.0;
+    }
+    let ln = (2.0 * ln) + (exponent * core::f64::consts::LN_2);
+
+    // Compute `exp(y * ln)` via the Taylor series of a scaled-down argument, and square the result back up
+    let argument = (y * ln) / 1024.0;
+    let (mut exp, mut term, mut n) = (1.0, 1.0, 1.0);
+    while n < 16.0 {
+        term *= argument / n;
+        exp += term;
+        n += 1.0;
+    }
+    let mut squarings = 0;
+    while squarings < 10 {
+        exp *= exp;
+        squarings += 1;
+    }
+    exp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_curves_match_powf() {
+        for (table, gamma) in [(Gamma::GAMMA22, 2.2), (Gamma::GAMMA28, 2.8)] {
+            for (index, entry) in table.iter().enumerate() {
+                let expected = (f64::powf(index as f64 / 255.0, gamma) * 65535.0).round() as i32;
+                assert!((*entry as i32 - expected).abs() <= 1, "entry {index} is {entry} instead of {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn curves_are_monotonic_and_bounded() {
+        for table in [Gamma::LINEAR, Gamma::GAMMA22, Gamma::GAMMA28, Gamma::CIE] {
+            assert_eq!((table[0], table[255]), (0, 0xFFFF));
+            assert!(table.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+
+        // 50.2% lightness is 18.6% luminance
+        assert_eq!(Gamma::CIE[128], 12_179);
+    }
+
+    #[test]
+    fn lookup_interpolates_between_entries() {
+        let gamma = Gamma::new(Curve::Gamma22);
+        for value in 0..=u8::MAX {
+            let [r, ..] = gamma.apply([value as u16 * 257; 4]);
+            assert_eq!(r, Gamma::GAMMA22[value as usize]);
+        }
+        for value in 0..=u16::MAX {
+            assert_eq!(Gamma::lookup(&Gamma::LINEAR, value), value);
+        }
+
+        let (low, high) = (Gamma::GAMMA28[100], Gamma::GAMMA28[101]);
+        let between = Gamma::lookup(&Gamma::GAMMA28, (100 * 257) + 128);
+        assert!(low < between && between < high);
+    }
+
+    #[test]
+    fn custom_table_is_applied() {
+        let mut gamma = Gamma::new(Curve::Custom);
+        assert_eq!(gamma.apply([0x1234, 0, 0xFFFF, 0x8000]), [0x1234, 0, 0xFFFF, 0x8000]);
+
+        gamma.custom = core::array::from_fn(|index| 0xFFFF - (index as u16 * 257));
+        assert_eq!(gamma.apply([0, 0xFFFF, 0x0101, 0]), [0xFFFF, 0, 0xFEFE, 0xFFFF]);
+        for code in [0, 1, 2, 3, 0xFF] {
+            assert_eq!(Curve::from_u8(code).map(Curve::to_u8), Some(code));
+        }
+    }
+}
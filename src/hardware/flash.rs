@@ -1,46 +1,37 @@
 //! Flash-related peripheral access
 
-// FIXME: the `rp2040_flash` is currently incompatible with the current HAL version
-// use rp2040_flash::flash;
-//
-// /// Gets the flash UID
-// ///
-// /// # Safety
-// /// Nothing must access flash while this is running. Usually this means:
-// /// - interrupts must be disabled
-// /// - 2nd core must be running code from RAM or ROM with interrupts disabled
-// /// - DMA must not access flash memory
-// pub unsafe fn uid() -> (u32, u64) {
-//     /// The vendor ID
-//     const VENDOR: u32 = const_int_from_compileenv!("WS2812B_UID_VENDOR" => u32, default: "51966"); // 0xCAFE
-//     /// The device ID
-//     const ID: u64 = const_int_from_compileenv!("WS2812B_UID_ID" => u32, default: "3735928559"); // 0xDEADBEEF
-//
-//     // Use compile-time vendor and ID if given
-//     if VENDOR != 0xCAFE || ID != 0xDEADBEEF {
-//         return (VENDOR, ID);
-//     }
-//
-//     // Get the JEDEC ID and prepare buffer
-//     let jedec_id = flash::flash_jedec_id(false);
-//     let mut flash_uid = [0; 8];
-//
-//     // Get the UID if the flash chip is from a well-known manufactor with support for UIDs
-//     #[allow(clippy::single_match)]
-//     match jedec_id {
-//         0xEF7015 => flash::flash_unique_id(&mut flash_uid, false),
-//         0xEF4015 => flash::flash_unique_id(&mut flash_uid, false),
-//         _ => (/* do nothing */),
-//     }
-//
-//     // Combine JEDEC and board UID
-//     let flash_uid = u64::from_ne_bytes(flash_uid);
-//     (jedec_id, flash_uid)
-// }
-
-use crate::const_int_from_compileenv;
-
-/// Gets the flash UID
+use crate::board::hal::rom_data;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::SeqCst;
+use core::{arch, ptr, slice};
+use ws2812b_driver::const_int_from_compileenv;
+use ws2812b_driver::settings::Store;
+
+/// The base address of the memory-mapped flash
+const XIP_BASE: usize = 0x1000_0000;
+/// The offset of the settings store within the flash (see `memory.x`)
+const STORE_OFFSET: usize = (2048 * 1024) - Store::SIZE;
+/// The block size for the ROM erase routine, which uses block erases for aligned ranges and sector erases otherwise
+const ERASE_BLOCK_SIZE: u32 = 1 << 16;
+/// The block erase command for the ROM erase routine
+const ERASE_BLOCK_COMMAND: u8 = 0xD8;
+/// The flash command to read the JEDEC ID
+const JEDEC_ID_COMMAND: u8 = 0x9F;
+/// The flash command to read the unique ID
+const UNIQUE_ID_COMMAND: u8 = 0x4B;
+/// The address of the QSPI chip select control register
+const QSPI_SS_CTRL: usize = 0x4001_800C;
+/// The QSPI chip select control value to force the chip select low
+const QSPI_SS_LOW: u32 = 0x2 << 8;
+/// The base address of the SSI peripheral
+const SSI_BASE: usize = 0x1800_0000;
+
+/// Whether core 1 is requested to park in RAM
+static PARK_REQUEST: AtomicBool = AtomicBool::new(false);
+/// Whether core 1 is parked in RAM
+static PARKED: AtomicBool = AtomicBool::new(false);
+
+/// Gets the JEDEC ID and the flash UID
 ///
 /// # Safety
 /// Nothing must access flash while this is running. Usually this means:
@@ -53,7 +44,242 @@ pub unsafe fn uid() -> (u32, u64) {
     /// The device ID
     const ID: u64 = const_int_from_compileenv!("WS2812B_UID_ID" => u64, default: "3735928559"); // 0xDEADBEEF
 
-    // FIXME: WORKAROUND: This is a no-op fallback since the `rp2040_flash` is currently incompatible with the current HAL
-    // version
-    (VENDOR, ID)
+    // Use compile-time vendor and ID if given
+    if VENDOR != 0xCAFE || ID != 0xDEADBEEF {
+        return (VENDOR, ID);
+    }
+
+    // Get the JEDEC ID
+    let mut jedec_id = [JEDEC_ID_COMMAND, 0, 0, 0];
+    command(&mut jedec_id);
+    let [_, manufacturer, memory_type, capacity] = jedec_id;
+    let jedec_id = u32::from_be_bytes([0, manufacturer, memory_type, capacity]);
+
+    // Get the UID if the flash chip is from a well-known manufactor with support for UIDs
+    // Note: The unique ID command is followed by 4 dummy bytes
+    let mut flash_uid = [0; 13];
+    flash_uid[0] = UNIQUE_ID_COMMAND;
+    #[allow(clippy::single_match)]
+    match jedec_id {
+        0xEF7015 => command(&mut flash_uid),
+        0xEF4015 => command(&mut flash_uid),
+        _ => (/* do nothing */),
+    }
+
+    // Combine JEDEC and board UID
+    let flash_uid = u64::from_ne_bytes(flash_uid[5..].try_into().expect("invalid flash UID length"));
+    (jedec_id, flash_uid)
+}
+
+/// Sends a raw command to the flash chip, where the buffer contains the command bytes and receives the response bytes
+///
+/// # Safety
+/// Nothing must access flash while this is running. Usually this means:
+/// - interrupts must be disabled
+/// - 2nd core must be running code from RAM or ROM with interrupts disabled
+/// - DMA must not access flash memory
+unsafe fn command(buf: &mut [u8]) {
+    let range = buf.as_mut_ptr_range();
+    let op = FlashOp { command: range.start, command_end: range.end, ..FlashOp::new() };
+    flash_op(&op);
+}
+
+/// Gets the memory-mapped settings store
+pub fn store() -> &'static [u8] {
+    // Safety: The store is a reserved flash region (see `memory.x`) that is only modified via `erase` and `program`
+    unsafe { slice::from_raw_parts((XIP_BASE + STORE_OFFSET) as *const u8, Store::SIZE) }
+}
+
+/// Erases the sector at the given offset within the settings store
+///
+/// # Safety
+/// Nothing must access flash while this is running. Usually this means:
+/// - interrupts must be disabled
+/// - 2nd core must be running code from RAM or ROM with interrupts disabled (see `park`)
+/// - DMA must not access flash memory
+pub unsafe fn erase(offset: usize) {
+    let address = (STORE_OFFSET + offset) as u32;
+    let op = FlashOp { address, erase_len: Store::SECTOR_SIZE, ..FlashOp::new() };
+    flash_op(&op);
+}
+
+/// Programs the page at the given offset within the settings store
+///
+/// # Safety
+/// Nothing must access flash while this is running. Usually this means:
+/// - interrupts must be disabled
+/// - 2nd core must be running code from RAM or ROM with interrupts disabled (see `park`)
+/// - DMA must not access flash memory
+pub unsafe fn program(offset: usize, page: &[u8; Store::PAGE_SIZE]) {
+    let address = (STORE_OFFSET + offset) as u32;
+    let op = FlashOp { address, data: page.as_ptr(), data_len: page.len(), ..FlashOp::new() };
+    flash_op(&op);
+}
+
+/// Parks the calling core in RAM until the park request is withdrawn
+///
+/// # Important
+/// This function must be called on core 1 after `request_park`, so that core 0 can safely access the flash.
+pub fn park() {
+    // Safety: The flags are static atomics and thus valid for the entire lifetime of the program
+    unsafe { park_ram(PARK_REQUEST.as_ptr() as *const u8, PARKED.as_ptr() as *mut u8) };
+}
+
+/// Requests core 1 to park in RAM
+pub fn request_park() {
+    PARK_REQUEST.store(true, SeqCst);
+}
+
+/// Whether core 1 is parked in RAM
+pub fn is_parked() -> bool {
+    PARKED.load(SeqCst)
+}
+
+/// Withdraws the park request and waits until core 1 has left the parking loop
+pub fn unpark() {
+    PARK_REQUEST.store(false, SeqCst);
+    cortex_m::asm::sev();
+    while PARKED.load(SeqCst) {
+        // Wait for core 1
+        core::hint::spin_loop();
+    }
+}
+
+/// The ROM routines and arguments of a flash operation
+///
+/// # Note
+/// All ROM routines are resolved in advance, since the ROM function table lookup is executed from flash.
+#[repr(C)]
+struct FlashOp {
+    /// Restores the QSPI pads and the SSI
+    connect_internal_flash: unsafe extern "C" fn(),
+    /// Leaves the XIP mode
+    flash_exit_xip: unsafe extern "C" fn(),
+    /// Erases a flash range
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    /// Programs a flash range
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    /// Flushes the XIP cache
+    flash_flush_cache: unsafe extern "C" fn(),
+    /// The RAM copy of the second stage bootloader, which restores the fast XIP mode
+    boot2: unsafe extern "C" fn(),
+    /// The flash address
+    address: u32,
+    /// The amount of bytes to erase
+    erase_len: usize,
+    /// The data to program
+    data: *const u8,
+    /// The amount of bytes to program
+    data_len: usize,
+    /// The start of the raw command buffer
+    command: *mut u8,
+    /// The end of the raw command buffer
+    command_end: *mut u8,
+}
+impl FlashOp {
+    /// Resolves the ROM routines and creates a new no-op flash operation
+    ///
+    /// # Safety
+    /// Nothing must access the flash concurrently, since the second stage bootloader is copied into RAM.
+    unsafe fn new() -> Self {
+        /// The RAM copy of the second stage bootloader
+        static mut BOOT2: [u32; 64] = [0; 64];
+
+        // Copy the second stage bootloader into RAM
+        let boot2 = ptr::addr_of_mut!(BOOT2) as *mut u32;
+        ptr::copy_nonoverlapping(XIP_BASE as *const u32, boot2, 64);
+        let boot2: unsafe extern "C" fn() = core::mem::transmute((boot2 as usize) | 1);
+
+        Self {
+            connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+            flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+            flash_range_erase: rom_data::flash_range_erase::ptr(),
+            flash_range_program: rom_data::flash_range_program::ptr(),
+            flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+            boot2,
+            address: 0,
+            erase_len: 0,
+            data: ptr::null(),
+            data_len: 0,
+            command: ptr::null_mut(),
+            command_end: ptr::null_mut(),
+        }
+    }
+}
+
+/// Performs a flash operation from RAM
+///
+/// # Safety
+/// Nothing must access flash while this is running.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe extern "C" fn flash_op(op: &FlashOp) {
+    // Note: This function must not call any code that lives in flash
+    (op.connect_internal_flash)();
+    (op.flash_exit_xip)();
+    if op.erase_len > 0 {
+        (op.flash_range_erase)(op.address, op.erase_len, ERASE_BLOCK_SIZE, ERASE_BLOCK_COMMAND);
+    }
+    if op.data_len > 0 {
+        (op.flash_range_program)(op.address, op.data, op.data_len);
+    }
+    if op.command != op.command_end {
+        // Force the chip select low and exchange the command buffer byte per byte via the SSI
+        // Note: The chip select is released by `flash_flush_cache`
+        arch::asm!(
+            "str {low}, [{ss_ctrl}]",
+            "2:",
+            "ldr {tmp}, [{ssi}, #0x28]",
+            "movs {mask}, #2",
+            "tst {tmp}, {mask}",
+            "beq 2b",
+            "ldrb {tmp}, [{buf}]",
+            "str {tmp}, [{ssi}, #0x60]",
+            "3:",
+            "ldr {tmp}, [{ssi}, #0x28]",
+            "movs {mask}, #8",
+            "tst {tmp}, {mask}",
+            "beq 3b",
+            "ldr {tmp}, [{ssi}, #0x60]",
+            "strb {tmp}, [{buf}]",
+            "adds {buf}, #1",
+            "cmp {buf}, {end}",
+            "bne 2b",
+            ss_ctrl = in(reg) QSPI_SS_CTRL,
+            low = in(reg) QSPI_SS_LOW,
+            ssi = in(reg) SSI_BASE,
+            buf = inout(reg) op.command => _,
+            end = in(reg) op.command_end,
+            tmp = out(reg) _,
+            mask = out(reg) _,
+        );
+    }
+    (op.flash_flush_cache)();
+    (op.boot2)();
+}
+
+/// Signals that the calling core is parked and waits in RAM until the park request is withdrawn
+///
+/// # Safety
+/// Both pointers must be valid.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe extern "C" fn park_ram(request: *const u8, parked: *mut u8) {
+    // Note: This loop is written in assembly to ensure that no code from flash is called
+    arch::asm!(
+        "movs {tmp}, #1",
+        "strb {tmp}, [{parked}]",
+        "dsb",
+        "2:",
+        "wfe",
+        "ldrb {tmp}, [{request}]",
+        "cmp {tmp}, #0",
+        "bne 2b",
+        "movs {tmp}, #0",
+        "strb {tmp}, [{parked}]",
+        "dsb",
+        request = in(reg) request,
+        parked = in(reg) parked,
+        tmp = out(reg) _,
+    );
 }
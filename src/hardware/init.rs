@@ -1,15 +1,22 @@
 //! Initializes all required hardware modules
 
 use crate::board::hal::clocks::{self, ClocksManager, SystemClock};
+use crate::board::hal::dma::{Channels, DMAExt};
 use crate::board::hal::gpio::{DynPinId, FunctionSioOutput, Pin, PullDown};
 use crate::board::hal::multicore::Multicore;
 use crate::board::hal::pio::{PIOExt, UninitStateMachine, PIO, SM0, SM1, SM2, SM3};
 use crate::board::hal::sio::SioFifo;
 use crate::board::hal::usb::UsbBus;
 use crate::board::hal::{Sio, Timer, Watchdog};
+#[cfg(feature = "pio1")]
+use crate::board::pac::PIO1;
 use crate::board::pac::{Peripherals, PIO0, PPB, PSM, RESETS};
 use crate::board::{Pins, XOSC_CRYSTAL_FREQ};
+use crate::hardware::flash;
+#[cfg(feature = "pio1")]
+use crate::hardware::pins::Pio1Pins;
 use crate::hardware::pins::{PinSet, Pio0Pins};
+use ws2812b_driver::settings::{Settings, Store};
 
 /// The hardware peripherals to start core 1
 pub struct Core1 {
@@ -34,26 +41,37 @@ impl Core1 {
     }
 }
 
-/// The PIO 0 engine
-pub struct Pio0 {
+/// A PIO engine
+pub struct PioEngine<P>
+where
+    P: PIOExt,
+{
     /// The PIO hardware
-    pub pio: PIO<PIO0>,
+    pub pio: PIO<P>,
     /// The unitialized state machine 0
-    pub sm0: UninitStateMachine<(PIO0, SM0)>,
+    pub sm0: UninitStateMachine<(P, SM0)>,
     /// The unitialized state machine 1
-    pub sm1: UninitStateMachine<(PIO0, SM1)>,
+    pub sm1: UninitStateMachine<(P, SM1)>,
     /// The unitialized state machine 2
-    pub sm2: UninitStateMachine<(PIO0, SM2)>,
+    pub sm2: UninitStateMachine<(P, SM2)>,
     /// The unitialized state machine 3
-    pub sm3: UninitStateMachine<(PIO0, SM3)>,
+    pub sm3: UninitStateMachine<(P, SM3)>,
 }
-impl Pio0 {
-    /// Creates a new PIO0 adapter from the given peripheral
-    pub fn new(pio0: PIO0, resets: &mut RESETS) -> Self {
-        let (pio, sm0, sm1, sm2, sm3) = pio0.split(resets);
+impl<P> PioEngine<P>
+where
+    P: PIOExt,
+{
+    /// Creates a new PIO adapter from the given peripheral
+    pub fn new(pio: P, resets: &mut RESETS) -> Self {
+        let (pio, sm0, sm1, sm2, sm3) = pio.split(resets);
         Self { pio, sm0, sm1, sm2, sm3 }
     }
 }
+/// The PIO 0 engine
+pub type Pio0 = PioEngine<PIO0>;
+/// The PIO 1 engine
+#[cfg(feature = "pio1")]
+pub type Pio1 = PioEngine<PIO1>;
 
 /// The underlying basic hardware
 pub struct Hardware {
@@ -71,6 +89,16 @@ pub struct Hardware {
     pub pio0: Pio0,
     /// The PIO0 associated pins
     pub pio0_pins: Pio0Pins,
+    /// The PIO1 peripheral
+    #[cfg(feature = "pio1")]
+    pub pio1: Pio1,
+    /// The PIO1 associated pins
+    #[cfg(feature = "pio1")]
+    pub pio1_pins: Pio1Pins,
+    /// The DMA channels
+    pub dma: Channels,
+    /// The persistent settings
+    pub settings: Settings,
 }
 impl Hardware {
     /// Initializes the required hardware
@@ -78,9 +106,12 @@ impl Hardware {
         // Get the required peripherals
         let Peripherals {
             CLOCKS,
+            DMA,
             IO_BANK0,
             PADS_BANK0,
             PIO0,
+            #[cfg(feature = "pio1")]
+            PIO1,
             PLL_SYS,
             PLL_USB,
             PPB,
@@ -105,10 +136,13 @@ impl Hardware {
         let timer = Timer::new(TIMER, &mut RESETS, &clocks);
         let ClocksManager { system_clock, usb_clock, .. } = clocks;
 
-        // Create basic IO and get pin set from compile time environment
+        // Load the persistent settings or fall back to the compile time environment
+        let settings = Store::load(flash::store()).unwrap_or(Settings::COMPILE_ENV);
+
+        // Create basic IO and get pin set from the settings
         let sio = Sio::new(SIO);
         let pins = Pins::new(IO_BANK0, PADS_BANK0, sio.gpio_bank0, &mut RESETS);
-        let pin_set = PinSet::from_compile_env(pins);
+        let pin_set = PinSet::new(pins, &settings.pins);
 
         // Init self
         Some(Self {
@@ -119,6 +153,12 @@ impl Hardware {
             core1: Core1::new(PPB, PSM, sio.fifo),
             pio0: Pio0::new(PIO0, &mut RESETS),
             pio0_pins: pin_set.pio0,
+            #[cfg(feature = "pio1")]
+            pio1: Pio1::new(PIO1, &mut RESETS),
+            #[cfg(feature = "pio1")]
+            pio1_pins: pin_set.pio1,
+            dma: DMA.split(&mut RESETS),
+            settings,
         })
     }
 }
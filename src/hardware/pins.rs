@@ -1,20 +1,34 @@
 //! Dynamic pin selection for PIO GPIO pins
 
+#[cfg(feature = "pio1")]
+use crate::board::hal::gpio::FunctionPio1;
 use crate::board::hal::gpio::{DynPinId, Function, FunctionNull, FunctionPio0, FunctionSioOutput, Pin, PullDown};
 use crate::board::Pins;
-use crate::const_int_from_compileenv;
+use ws2812b_driver::settings::PinConfig;
 
-/// The PIO pins
-pub struct Pio0Pins {
+/// The pins of a PIO engine
+pub struct PioPins<F>
+where
+    F: Function,
+{
     /// PIO pin A
-    pub pin_a: Pin<DynPinId, FunctionPio0, PullDown>,
+    pub pin_a: Pin<DynPinId, F, PullDown>,
     /// PIO pin B
-    pub pin_b: Pin<DynPinId, FunctionPio0, PullDown>,
+    pub pin_b: Pin<DynPinId, F, PullDown>,
     /// PIO pin C
-    pub pin_c: Pin<DynPinId, FunctionPio0, PullDown>,
+    pub pin_c: Pin<DynPinId, F, PullDown>,
     /// PIO pin D
-    pub pin_d: Pin<DynPinId, FunctionPio0, PullDown>,
+    pub pin_d: Pin<DynPinId, F, PullDown>,
 }
+/// The PIO 0 pins
+pub type Pio0Pins = PioPins<FunctionPio0>;
+/// The PIO 1 pins
+#[cfg(feature = "pio1")]
+pub type Pio1Pins = PioPins<FunctionPio1>;
+
+/// Rejects compile-time pin assignments that are not available on the board
+const _: () =
+    assert!(PinSet::is_available(&PinConfig::COMPILE_ENV), "invalid compile-time pin assignment for this board");
 
 /// The compile-time specified pin set
 pub struct PinSet {
@@ -22,42 +36,89 @@ pub struct PinSet {
     pub led: Pin<DynPinId, FunctionSioOutput, PullDown>,
     /// Pins for PIO 0
     pub pio0: Pio0Pins,
+    /// Pins for PIO 1
+    #[cfg(feature = "pio1")]
+    pub pio1: Pio1Pins,
 }
 impl PinSet {
-    /// Gets the pin set from environment
-    pub fn from_compile_env(pins: Pins) -> Self {
-        /// PIO0 pin 0
-        const PIO0_PIN0: u8 = const_int_from_compileenv!("WS2812B_PIO0_PIN0" => u8, default: "10");
-        /// PIO0 pin 1
-        const PIO0_PIN1: u8 = const_int_from_compileenv!("WS2812B_PIO0_PIN1" => u8, default: "11");
-        /// PIO0 pin 2
-        const PIO0_PIN2: u8 = const_int_from_compileenv!("WS2812B_PIO0_PIN2" => u8, default: "12");
-        /// PIO0 pin 3
-        const PIO0_PIN3: u8 = const_int_from_compileenv!("WS2812B_PIO0_PIN3" => u8, default: "13");
-        /// The LED pin
-        const GPIO_LED: u8 = const_int_from_compileenv!("WS2812B_GPIO_LED" => u8, default: "25");
-
+    /// Gets the pin set from the given pin assignment, or from the compile-time environment if the given pins are not
+    /// available
+    pub fn new(pins: Pins, config: &PinConfig) -> Self {
         /// Helper function to configure a dynamic pin
-        fn get_pin<T>(pin: &mut Option<Pin<DynPinId, FunctionNull, PullDown>>) -> Pin<DynPinId, T, PullDown>
+        fn get_pin<T>(pins: &mut [Option<Pin<DynPinId, FunctionNull, PullDown>>], num: u8) -> Pin<DynPinId, T, PullDown>
         where
             T: Function,
         {
             // Configure pin
+            let pin = pins.get_mut(num as usize).expect("invalid pin number");
             let pin = pin.take().expect("pin is already in use");
             pin.try_into_function().unwrap_or_else(|_| panic!("failed to configure pin"))
         }
 
-        // Init self
+        // Fall back to the compile-time pin assignment if the pins are not available
         let mut pins = Self::index_set(pins);
+        let config = match Self::is_available(config) {
+            true => config,
+            false => &PinConfig::COMPILE_ENV,
+        };
+
+        // Init self
+        let pins = pins.as_mut();
+        let [pio0_a, pio0_b, pio0_c, pio0_d] = config.pio0;
         let pio0 = Pio0Pins {
-            pin_a: get_pin(pins.as_mut().get_mut(PIO0_PIN0 as usize).expect("invalid pin number")),
-            pin_b: get_pin(pins.as_mut().get_mut(PIO0_PIN1 as usize).expect("invalid pin number")),
-            pin_c: get_pin(pins.as_mut().get_mut(PIO0_PIN2 as usize).expect("invalid pin number")),
-            pin_d: get_pin(pins.as_mut().get_mut(PIO0_PIN3 as usize).expect("invalid pin number")),
+            pin_a: get_pin(pins, pio0_a),
+            pin_b: get_pin(pins, pio0_b),
+            pin_c: get_pin(pins, pio0_c),
+            pin_d: get_pin(pins, pio0_d),
+        };
+        #[cfg(feature = "pio1")]
+        let [pio1_a, pio1_b, pio1_c, pio1_d] = config.pio1;
+        #[cfg(feature = "pio1")]
+        let pio1 = Pio1Pins {
+            pin_a: get_pin(pins, pio1_a),
+            pin_b: get_pin(pins, pio1_b),
+            pin_c: get_pin(pins, pio1_c),
+            pin_d: get_pin(pins, pio1_d),
+        };
+        Self {
+            led: get_pin(pins, config.led),
+            pio0,
+            #[cfg(feature = "pio1")]
+            pio1,
+        }
+    }
+
+    /// Whether all pins of the pin assignment are available on the board and distinct
+    const fn is_available(config: &PinConfig) -> bool {
+        // Collect all used pins
+        let [pio0_a, pio0_b, pio0_c, pio0_d] = config.pio0;
+        let [pio1_a, pio1_b, pio1_c, pio1_d] = config.pio1;
+        let nums = [config.led, pio0_a, pio0_b, pio0_c, pio0_d, pio1_a, pio1_b, pio1_c, pio1_d];
+        let count = match cfg!(feature = "pio1") {
+            true => nums.len(),
+            false => 5,
         };
-        Self { led: get_pin(pins.as_mut().get_mut(GPIO_LED as usize).expect("invalid pin number")), pio0 }
+
+        let (mut used, mut pos) = (0u32, 0);
+        while pos < count {
+            // Ensure that the pin exists and is not used yet
+            let num = nums[pos];
+            pos += 1;
+            if num >= 32 || Self::AVAILABLE & (1 << num) == 0 || used & (1 << num) != 0 {
+                return false;
+            }
+            used |= 1 << num;
+        }
+        true
     }
 
+    /// The GPIO pins that are available on the board as bitmask
+    #[cfg(feature = "raspberrypi-pico")]
+    const AVAILABLE: u32 = 0b0001_1110_0111_1111_1111_1111_1111_1111;
+    /// The GPIO pins that are available on the board as bitmask
+    #[cfg(feature = "seeduino-xiao")]
+    const AVAILABLE: u32 = 0b0000_0010_0000_0011_0000_0111_1111_1111;
+
     /// Creates an indexed set from the GPIO pins
     #[cfg(feature = "raspberrypi-pico")]
     fn index_set(pins: Pins) -> impl AsMut<[Option<Pin<DynPinId, FunctionNull, PullDown>>]> {
@@ -11,9 +11,10 @@ use usb_device::prelude::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
 use usb_device::LangID;
 use usb_device::UsbError::WouldBlock;
 use usbd_serial::SerialPort;
+use ws2812b_driver::ack::{Ack, AckMode, Acknowledger};
+use ws2812b_driver::line::LineBuffer;
+use ws2812b_driver::settings::UsbIdentity;
 
-/// The USB vendor ID
-const VID: (u16, u16) = (0x16c0, 0x27dd);
 /// The USB manufacturer
 const MANUFACTURER: &str = "KizzyCode Software Labs./Keziah Biermann";
 /// The USB product
@@ -27,12 +28,14 @@ pub struct UsbSerialDevice {
     device: UsbDevice<'static, UsbBus>,
     /// The USB device as serial device
     serial: SerialPort<'static, UsbBus>,
+    /// The acknowledgement state of processed commands
+    acks: Acknowledger,
     /// Deny send and sync
     _nosendsync: PhantomData<*const OnceCell<(UsbBusAllocator<UsbBus>, StrBuffer<64>)>>,
 }
 impl UsbSerialDevice {
-    /// Creates a new USB serial device on the given USB bus
-    pub fn new(usb_bus: UsbBus, serno: StrBuffer<64>) -> Self {
+    /// Creates a new USB serial device with the given identity on the given USB bus
+    pub fn new(usb_bus: UsbBus, serno: StrBuffer<64>, identity: &UsbIdentity) -> Self {
         /// The USB allocator
         ///
         /// # Safety
@@ -55,7 +58,7 @@ impl UsbSerialDevice {
         let serno = SERNO.init(serno);
 
         // Initialize the USB device
-        let vid_pid = UsbVidPid(VID.0, VID.1);
+        let vid_pid = UsbVidPid(identity.vid, identity.pid);
         let serial = SerialPort::new(allocator);
         let descriptors =
             StringDescriptors::new(LangID::DE).serial_number(serno).manufacturer(MANUFACTURER).product(PRODUCT);
@@ -66,7 +69,7 @@ impl UsbSerialDevice {
             .device_class(CLASS)
             .build();
 
-        Self { device, serial, _nosendsync: PhantomData }
+        Self { device, serial, acks: Acknowledger::new(AckMode::COMPILE_ENV), _nosendsync: PhantomData }
     }
 
     /// Polls the USB devices
@@ -85,25 +88,53 @@ impl UsbSerialDevice {
         let _ = self.device.force_reset();
     }
 
-    /// Reads byte per byte until the `condition` evaluates to true
-    pub async fn read_until<F>(&mut self, buf: &mut [u8], mut condition: F)
-    where
-        F: FnMut(&[u8]) -> bool,
-    {
-        'read_loop: while !condition(buf) {
+    /// Reads byte per byte until the line is complete
+    pub async fn read_line(&mut self, line: &mut LineBuffer) {
+        loop {
             // Always yield here to avoid a tight loop
             embedded_runtime_rp2040::spin_once().await;
             self.poll();
 
             // Read the next byte if available, otherwise try again
-            match self.serial.read(&mut buf[..1]) {
-                Ok(_) => buf.rotate_left(1),
-                Err(WouldBlock) => continue 'read_loop,
+            let mut byte = [0; 1];
+            match self.serial.read(&mut byte) {
+                Ok(0) | Err(WouldBlock) => continue,
+                Ok(_) if line.push(byte[0]) => return,
+                Ok(_) => continue,
                 Err(e) => panic!("failed to read from USB device ({e:?})"),
             }
         }
     }
 
+    /// Reads until the entire buffer is filled
+    pub async fn read_exact(&mut self, buf: &mut [u8]) {
+        // Fill the entire buffer
+        let mut buf_pos = 0;
+        while buf_pos < buf.len() {
+            // Always yield here to avoid a tight loop
+            embedded_runtime_rp2040::spin_once().await;
+            self.poll();
+
+            // Read the next data
+            buf_pos += match self.serial.read(&mut buf[buf_pos..]) {
+                Ok(len) => len,
+                Err(WouldBlock) => 0,
+                Err(e) => panic!("failed to read from USB device ({e:?})"),
+            };
+        }
+    }
+
+    /// Reads and discards the given amount of bytes
+    pub async fn skip(&mut self, mut len: usize) {
+        let mut buf = [0; 64];
+        while len > 0 {
+            // Discard the next chunk
+            let to_skip = core::cmp::min(len, buf.len());
+            self.read_exact(&mut buf[..to_skip]).await;
+            len -= to_skip;
+        }
+    }
+
     /// Writes the entire buffer
     pub async fn write_all(&mut self, buf: &[u8]) {
         // Write the entire buffer
@@ -121,4 +152,19 @@ impl UsbSerialDevice {
             };
         }
     }
+
+    /// Selects the acknowledgement mode for all subsequent commands
+    pub fn set_ack_mode(&mut self, mode: AckMode) {
+        self.acks.set_mode(mode);
+    }
+
+    /// Acknowledges a processed command according to the acknowledgement mode, where `echo` is the command line or
+    /// frame header to reflect in echo mode
+    pub async fn acknowledge(&mut self, echo: &[u8]) {
+        match self.acks.register() {
+            Ack::Echo => self.write_all(echo).await,
+            Ack::Compact(ack) => self.write_all(&ack).await,
+            Ack::None => (/* nothing to send */),
+        }
+    }
 }
@@ -0,0 +1,111 @@
+//! A bounded buffer for newline-terminated command lines
+
+/// A bounded buffer for a single newline-terminated command line
+///
+/// # Note
+/// If a line exceeds the capacity, the remaining bytes are discarded up to and including the next newline, so that the
+/// next line starts in sync again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineBuffer {
+    /// The buffered bytes
+    data: [u8; Self::CAPACITY],
+    /// The amount of buffered bytes
+    len: usize,
+    /// Whether bytes have been discarded because the line exceeds the capacity
+    overflow: bool,
+}
+impl LineBuffer {
+    /// The maximum size of a command line including the newline
+    pub const CAPACITY: usize = 64;
+
+    /// Creates a new empty line buffer
+    pub const fn new() -> Self {
+        Self { data: [0; Self::CAPACITY], len: 0, overflow: false }
+    }
+
+    /// Appends the next byte and returns whether the line is complete
+    pub fn push(&mut self, byte: u8) -> bool {
+        // Note: The last slot is reserved for the newline
+        let limit = match byte {
+            b'\n' => Self::CAPACITY,
+            _ => Self::CAPACITY - 1,
+        };
+
+        // Append the byte or discard it if the line exceeds the capacity
+        match self.overflow || self.len >= limit {
+            true => self.overflow = true,
+            false => {
+                self.data[self.len] = byte;
+                self.len += 1;
+            }
+        }
+        byte == b'\n'
+    }
+
+    /// Appends the bytes and returns whether the line is complete
+    ///
+    /// # Important
+    /// The bytes must not contain anything after the newline, since all bytes are appended to this line.
+    pub fn extend(&mut self, data: &[u8]) -> bool {
+        data.iter().fold(false, |_, byte| self.push(*byte))
+    }
+
+    /// The complete line including the newline, or `None` if the line exceeded the capacity
+    pub fn line(&self) -> Option<&[u8]> {
+        match self.overflow {
+            true => None,
+            false => Some(&self.data[..self.len]),
+        }
+    }
+
+    /// The buffered bytes, which are the beginning of the line if the line exceeded the capacity
+    pub fn head(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pushes the bytes and returns whether the line is complete after each byte
+    fn push_all(buffer: &mut LineBuffer, data: &[u8]) -> Vec<bool> {
+        data.iter().map(|byte| buffer.push(*byte)).collect()
+    }
+
+    #[test]
+    fn lines_are_terminated_by_newline() {
+        let mut buffer = LineBuffer::new();
+        assert_eq!(push_all(&mut buffer, b"SHOW\n"), [false, false, false, false, true]);
+        assert_eq!(buffer.line(), Some(&b"SHOW\n"[..]));
+
+        let mut buffer = LineBuffer::new();
+        assert!(!buffer.extend(b"AC"));
+        assert!(buffer.extend(b"K ECHO\n"));
+        assert_eq!(buffer.line(), Some(&b"ACK ECHO\n"[..]));
+
+        // The maximum line fills the entire buffer
+        let mut line = [b'x'; LineBuffer::CAPACITY];
+        line[LineBuffer::CAPACITY - 1] = b'\n';
+        let mut buffer = LineBuffer::new();
+        assert_eq!(push_all(&mut buffer, &line).iter().filter(|complete| **complete).count(), 1);
+        assert_eq!(buffer.line(), Some(&line[..]));
+    }
+
+    #[test]
+    fn overlong_lines_are_discarded() {
+        let mut buffer = LineBuffer::new();
+        let completed = push_all(&mut buffer, &[b'x'; 200]);
+        assert!(completed.iter().all(|complete| !complete));
+        assert!(buffer.push(b'\n'));
+
+        // The head is kept, but the line is rejected
+        assert_eq!(buffer.line(), None);
+        assert_eq!(buffer.head(), [b'x'; LineBuffer::CAPACITY - 1]);
+    }
+}
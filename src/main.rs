@@ -1,7 +1,6 @@
 #![no_std]
 #![no_main]
 
-mod command;
 mod hardware;
 mod panic;
 mod strbuffer;
@@ -24,20 +23,45 @@ use crate::hardware::flash;
 use crate::hardware::init::{Core1, Hardware};
 use crate::strbuffer::StrBuffer;
 use crate::tasks::{heartbeat, serial};
-use crate::ws2812b::{Core1Hardware, CORE1_HARDWARE};
+use crate::ws2812b::{Core1Hardware, Core1Sender, IpcRing, CORE1_HARDWARE};
 use core::fmt::Write;
+use static_cell::ConstStaticCell;
 
 #[board::entry]
 fn main() -> ! {
-    // Get the flash UID before doing anything else and build the serial number
+    // Get the flash UID before doing anything else
     let (jedec_id, flash_uid) = critical_section::with(|_| unsafe { flash::uid() });
-    let mut serno: StrBuffer<64> = StrBuffer::new();
-    write!(&mut serno, "WS2812B-0001-{jedec_id:08X}-{flash_uid:016X}").expect("failed to build serial number");
+
+    // Create the inter-core ring buffer
+    /// The inter-core ring buffer (which is too large to be allocated on the stack)
+    static IPC_RING: ConstStaticCell<IpcRing> = ConstStaticCell::new(IpcRing::new());
+    let (producer, consumer) = IPC_RING.take().split();
 
     // Initalize the hardware
     let mut hardware = Hardware::init().expect("failed to initialize hardware");
-    let core1_hardware =
-        Core1Hardware { system_clock: hardware.system_clock, pio0: hardware.pio0, pio0_pins: hardware.pio0_pins };
+    let settings = hardware.settings;
+
+    // Build the serial number, where a device ID from the settings overrides the flash UID
+    let device_id = match settings.usb.serial {
+        0 => flash_uid,
+        serial => serial,
+    };
+    let mut serno: StrBuffer<64> = StrBuffer::new();
+    write!(&mut serno, "WS2812B-0001-{jedec_id:08X}-{device_id:016X}").expect("failed to build serial number");
+    let core1_hardware = Core1Hardware {
+        system_clock: hardware.system_clock,
+        pio0: hardware.pio0,
+        pio0_pins: hardware.pio0_pins,
+        #[cfg(feature = "pio1")]
+        pio1: hardware.pio1,
+        #[cfg(feature = "pio1")]
+        pio1_pins: hardware.pio1_pins,
+        dma: hardware.dma,
+        timer: hardware.timer,
+        consumer,
+        configs: settings.strips,
+        brightness_limit: settings.brightness_limit,
+    };
 
     // Start core 1
     critical_section::with(|cs| CORE1_HARDWARE.replace(cs, Some(core1_hardware)));
@@ -45,14 +69,15 @@ fn main() -> ! {
 
     // Get the required peripherals for our main tasks
     let Hardware { timer, usb_bus, mut led, core1, .. } = hardware;
-    let Core1 { mut sio_fifo, .. } = core1;
+    let Core1 { sio_fifo, .. } = core1;
+    let mut core1_sender = Core1Sender::new(producer, sio_fifo);
 
     // Run our tasks
     let result = embedded_runtime_rp2040::run! {
         // Heartbeat task
         heartbeat::task(&mut led, &timer),
         // The main control task
-        serial::task(usb_bus, serno, &mut sio_fifo)
+        serial::task(usb_bus, serno, settings, &mut core1_sender)
     };
     panic!("the executor failed ({result:?})");
 }
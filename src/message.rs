@@ -0,0 +1,308 @@
+//! An inter-core message
+
+use crate::command::{Color, Command, DecodeError};
+use crate::config::{ColorOrder, StripConfig, Timing};
+use crate::gamma::Curve;
+
+/// An inter-core message that is sent as a sequence of 32 bit words
+///
+/// # Encoding
+/// Each message starts with a header word (indices are bit offsets), followed by the payload words (if any):
+///  - `[28, 32)`: The message tag
+///  - `[24, 28)`: The index of the LED strip (for updates and configurations only)
+///  - `[0, 24)`: The index of the LED pixel (for updates), the strip length (for configurations), the brightness (for
+///    brightness changes, where bit `8` marks the global brightness), the curve code (for gamma curves), the entry
+///    `index << 16 | value` (for custom gamma table entries), bit `8` to mark the total budget (for power budget
+///    changes) or the control word (for control messages)
+///
+/// The payload of an 8 bit update is a single word `r << 24 | g << 16 | b << 8 | w`, and the payload of a 16 bit update
+/// consists of the two words `r << 16 | g` and `b << 16 | w`, so that no color information is lost during transport.
+/// The payload of a configuration consists of the three words `timing << 16 | order << 8 | enabled << 1 | rgbw`,
+/// `t0h << 16 | t1h` and `tl`, where `timing` is the timing profile and `t0h`, `t1h` and `tl` are the custom timing
+/// values (see `Timing`). The payload of a power budget change is a single word with the budget in mA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    /// A pixel update that is written into the back buffer
+    Update(Command),
+    /// Latches the back buffer and refreshes all strips
+    Show,
+    /// Enables or disables the immediate mode, where every update is shown without an explicit `Show`
+    SetImmediate(bool),
+    /// Reconfigures the strip with the given index
+    Configure(usize, StripConfig),
+    /// Parks the receiving core in RAM so that the flash can be written safely
+    Park,
+    /// Sets the brightness of the strip with the given index, or the global brightness if the index is `None`
+    SetBrightness(Option<usize>, u8),
+    /// Selects the gamma curve
+    SetCurve(Curve),
+    /// Sets the entry with the given index of the custom gamma table
+    SetGammaEntry(u8, u16),
+    /// Sets the power budget in mA of the strip with the given index, or the total budget if the index is `None`
+    SetPowerBudget(Option<usize>, u32),
+    /// Enables or disables the temporal dithering
+    SetDither(bool),
+}
+impl Message {
+    /// The maximum amount of words per message
+    pub const WORDS_MAX: usize = 4;
+
+    /// The tag for an update with 8 bit per channel
+    const TAG_RGBW8: u32 = 0x0;
+    /// The tag for an update with 16 bit per channel
+    const TAG_RGBW16: u32 = 0x1;
+    /// The tag for a strip configuration
+    const TAG_CONFIG: u32 = 0x2;
+    /// The tag for a brightness change
+    const TAG_BRIGHTNESS: u32 = 0x3;
+    /// The header bit that marks a global brightness or power budget change
+    const GLOBAL: usize = 0x100;
+    /// The tag for a gamma curve selection
+    const TAG_CURVE: u32 = 0x4;
+    /// The tag for a custom gamma table entry
+    const TAG_GAMMA_ENTRY: u32 = 0x5;
+    /// The tag for a power budget change
+    const TAG_POWER_BUDGET: u32 = 0x6;
+    /// The tag for a control message
+    const TAG_CONTROL: u32 = 0xF;
+    /// The control word for `Show`
+    const SHOW: u32 = 0x01;
+    /// The control word for `SetImmediate(false)`
+    const IMMEDIATE_OFF: u32 = 0x02;
+    /// The control word for `SetImmediate(true)`
+    const IMMEDIATE_ON: u32 = 0x03;
+    /// The control word for `Park`
+    const PARK: u32 = 0x04;
+    /// The control word for `SetDither(false)`
+    const DITHER_OFF: u32 = 0x05;
+    /// The control word for `SetDither(true)`
+    const DITHER_ON: u32 = 0x06;
+
+    /// Decodes a serial command line including the newline and validates it against the given strip configurations
+    pub fn from_serial(data: &[u8], configs: &[StripConfig]) -> Result<Self, DecodeError> {
+        match data {
+            b"SHOW\n" => Ok(Self::Show),
+            b"MODE LATCHED\n" => Ok(Self::SetImmediate(false)),
+            b"MODE IMMEDIATE\n" => Ok(Self::SetImmediate(true)),
+            data => Command::from_serial(data, configs).map(Self::Update),
+        }
+    }
+
+    /// Decodes a message from the header word, where `next` reads the next payload word if necessary
+    pub fn from_words<F>(header: u32, mut next: F) -> Option<Self>
+    where
+        F: FnMut() -> u32,
+    {
+        // Destructure the header
+        let tag = header >> 28;
+        let strip = ((header >> 24) & 0xF) as usize;
+        let pixel = (header & 0xFF_FFFF) as usize;
+
+        // Decode the payload
+        match tag {
+            Self::TAG_RGBW8 => {
+                let [r, g, b, w] = next().to_be_bytes();
+                Some(Self::Update(Command { strip, pixel, color: Color::Rgbw8([r, g, b, w]) }))
+            }
+            Self::TAG_RGBW16 => {
+                let (rg, bw) = (next(), next());
+                let rgbw = [(rg >> 16) as u16, rg as u16, (bw >> 16) as u16, bw as u16];
+                Some(Self::Update(Command { strip, pixel, color: Color::Rgbw16(rgbw) }))
+            }
+            Self::TAG_CONFIG => {
+                let [_, timing, order, flags] = next().to_be_bytes();
+                let [t0h0, t0h1, t1h0, t1h1] = next().to_be_bytes();
+                let [_, _, tl0, tl1] = next().to_be_bytes();
+                let timing = Timing::from_serial([timing, t0h0, t0h1, t1h0, t1h1, tl0, tl1]);
+                let (order, timing) = (ColorOrder::from_u8(order)?, timing?);
+                let (rgbw, enabled) = (flags & 0b01 != 0, flags & 0b10 != 0);
+                Some(Self::Configure(strip, StripConfig { len: pixel, rgbw, order, timing, enabled }))
+            }
+            Self::TAG_BRIGHTNESS => match pixel & Self::GLOBAL {
+                0 => Some(Self::SetBrightness(Some(strip), pixel as u8)),
+                _ => Some(Self::SetBrightness(None, pixel as u8)),
+            },
+            Self::TAG_CURVE => Some(Self::SetCurve(Curve::from_u8(pixel as u8)?)),
+            Self::TAG_GAMMA_ENTRY => Some(Self::SetGammaEntry((pixel >> 16) as u8, pixel as u16)),
+            Self::TAG_POWER_BUDGET => match pixel & Self::GLOBAL {
+                0 => Some(Self::SetPowerBudget(Some(strip), next())),
+                _ => Some(Self::SetPowerBudget(None, next())),
+            },
+            Self::TAG_CONTROL => match header & 0xFF_FFFF {
+                Self::SHOW => Some(Self::Show),
+                Self::IMMEDIATE_OFF => Some(Self::SetImmediate(false)),
+                Self::IMMEDIATE_ON => Some(Self::SetImmediate(true)),
+                Self::PARK => Some(Self::Park),
+                Self::DITHER_OFF => Some(Self::SetDither(false)),
+                Self::DITHER_ON => Some(Self::SetDither(true)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    /// Encodes the message into up to `WORDS_MAX` words and returns the amount of words to send
+    ///
+    /// # Important
+    /// If the strip or pixel index of an update or the strip length of a configuration exceeds its field, the values are
+    /// silently truncated.
+    pub const fn to_words(self) -> ([u32; Self::WORDS_MAX], usize) {
+        /// Creates a header word
+        const fn header(tag: u32, strip: usize, pixel: usize) -> u32 {
+            (tag << 28) | (((strip & 0xF) as u32) << 24) | ((pixel & 0xFF_FFFF) as u32)
+        }
+
+        match self {
+            Self::Update(Command { strip, pixel, color: Color::Rgbw8(rgbw) }) => {
+                ([header(Self::TAG_RGBW8, strip, pixel), u32::from_be_bytes(rgbw), 0, 0], 2)
+            }
+            Self::Update(Command { strip, pixel, color: Color::Rgbw16([r, g, b, w]) }) => {
+                let (rg, bw) = (((r as u32) << 16) | (g as u32), ((b as u32) << 16) | (w as u32));
+                ([header(Self::TAG_RGBW16, strip, pixel), rg, bw, 0], 3)
+            }
+            Self::Show => ([header(Self::TAG_CONTROL, 0, Self::SHOW as usize), 0, 0, 0], 1),
+            Self::SetImmediate(false) => ([header(Self::TAG_CONTROL, 0, Self::IMMEDIATE_OFF as usize), 0, 0, 0], 1),
+            Self::SetImmediate(true) => ([header(Self::TAG_CONTROL, 0, Self::IMMEDIATE_ON as usize), 0, 0, 0], 1),
+            Self::Park => ([header(Self::TAG_CONTROL, 0, Self::PARK as usize), 0, 0, 0], 1),
+            Self::SetDither(false) => ([header(Self::TAG_CONTROL, 0, Self::DITHER_OFF as usize), 0, 0, 0], 1),
+            Self::SetDither(true) => ([header(Self::TAG_CONTROL, 0, Self::DITHER_ON as usize), 0, 0, 0], 1),
+            Self::SetBrightness(Some(strip), level) => {
+                ([header(Self::TAG_BRIGHTNESS, strip, level as usize), 0, 0, 0], 1)
+            }
+            Self::SetBrightness(None, level) => {
+                ([header(Self::TAG_BRIGHTNESS, 0, Self::GLOBAL | level as usize), 0, 0, 0], 1)
+            }
+            Self::SetPowerBudget(Some(strip), budget) => ([header(Self::TAG_POWER_BUDGET, strip, 0), budget, 0, 0], 2),
+            Self::SetPowerBudget(None, budget) => ([header(Self::TAG_POWER_BUDGET, 0, Self::GLOBAL), budget, 0, 0], 2),
+            Self::SetCurve(curve) => ([header(Self::TAG_CURVE, 0, curve.to_u8() as usize), 0, 0, 0], 1),
+            Self::SetGammaEntry(index, value) => {
+                let entry = ((index as usize) << 16) | (value as usize);
+                ([header(Self::TAG_GAMMA_ENTRY, 0, entry), 0, 0, 0], 1)
+            }
+            Self::Configure(strip, StripConfig { len, rgbw, order, timing, enabled }) => {
+                let flags = ((enabled as u32) << 1) | (rgbw as u32);
+                let [timing, t0h0, t0h1, t1h0, t1h1, tl0, tl1] = timing.to_serial();
+                let payload = ((timing as u32) << 16) | ((order.to_u8() as u32) << 8) | flags;
+                let (t0h_t1h, tl) =
+                    (u32::from_be_bytes([t0h0, t0h1, t1h0, t1h1]), u32::from_be_bytes([0, 0, tl0, tl1]));
+                ([header(Self::TAG_CONFIG, strip, len), payload, t0h_t1h, tl], 4)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes and decodes the message
+    fn round_trip(message: Message) -> Message {
+        let (words, len) = message.to_words();
+        let mut payload = words[1..len].iter().copied();
+        let decoded = Message::from_words(words[0], || payload.next().expect("missing payload word"));
+        assert_eq!(payload.next(), None, "unused payload word");
+        decoded.expect("failed to decode message")
+    }
+
+    #[test]
+    fn rgbw8_is_lossless() {
+        for value in 0..=u8::MAX {
+            for rgbw in [[value, 0, 0, 0], [0, value, 0, 0], [0, 0, value, 0], [0, 0, 0, value], [value; 4]] {
+                let message = Message::Update(Command { strip: 3, pixel: 511, color: Color::Rgbw8(rgbw) });
+                assert_eq!(round_trip(message), message);
+            }
+        }
+    }
+
+    #[test]
+    fn rgbw16_is_lossless() {
+        for value in 0..=u16::MAX {
+            let rgbw = [value, value.rotate_left(4), value.rotate_left(8), value.rotate_left(12)];
+            let message = Message::Update(Command { strip: 2, pixel: 17, color: Color::Rgbw16(rgbw) });
+            assert_eq!(round_trip(message), message);
+        }
+    }
+
+    #[test]
+    fn indices_are_preserved() {
+        for strip in 0..=0xF {
+            for pixel in [0, 1, 0x1FF, 0x200, 0xFFFF, 0xFF_FFFF] {
+                let message = Message::Update(Command { strip, pixel, color: Color::Rgbw8([1, 2, 3, 4]) });
+                assert_eq!(round_trip(message), message);
+            }
+        }
+    }
+
+    #[test]
+    fn control_messages() {
+        let messages = [
+            Message::Show,
+            Message::SetImmediate(false),
+            Message::SetImmediate(true),
+            Message::Park,
+            Message::SetDither(false),
+            Message::SetDither(true),
+        ];
+        for message in messages {
+            assert_eq!(round_trip(message), message);
+        }
+    }
+
+    #[test]
+    fn configurations_are_lossless() {
+        for (strip, len, rgbw, enabled) in [(0, 0, false, false), (3, 1500, true, true), (7, 0xFF_FFFF, false, true)] {
+            for timing in [Timing::Ws2812b, Timing::Ws2813, Timing::Custom { t0h: 300, t1h: 0xFFFF, tl: 0xFFFE }] {
+                let config = StripConfig { rgbw, enabled, timing, ..StripConfig::new(len, false) };
+                let message = Message::Configure(strip, config);
+                assert_eq!(round_trip(message), message);
+            }
+        }
+    }
+
+    #[test]
+    fn brightness_changes_are_lossless() {
+        for level in 0..=u8::MAX {
+            for strip in [None, Some(0), Some(7), Some(0xF)] {
+                let message = Message::SetBrightness(strip, level);
+                assert_eq!(round_trip(message), message);
+            }
+        }
+    }
+
+    #[test]
+    fn power_budgets_are_lossless() {
+        for budget in [0, 1, 500, 0xFFFF_FFFF] {
+            for strip in [None, Some(0), Some(7)] {
+                let message = Message::SetPowerBudget(strip, budget);
+                assert_eq!(round_trip(message), message);
+            }
+        }
+    }
+
+    #[test]
+    fn gamma_messages_are_lossless() {
+        for curve in [Curve::Linear, Curve::Gamma22, Curve::Gamma28, Curve::Cie, Curve::Custom] {
+            assert_eq!(round_trip(Message::SetCurve(curve)), Message::SetCurve(curve));
+        }
+        for (index, value) in [(0, 0), (1, 0xFFFF), (0x80, 0x1234), (0xFF, 0xFFFF)] {
+            let message = Message::SetGammaEntry(index, value);
+            assert_eq!(round_trip(message), message);
+        }
+    }
+
+    #[test]
+    fn narrow_restores_8bit_values() {
+        for value in 0..=u8::MAX {
+            let [r, g, b, w] = Color::Rgbw8([value; 4]).to_rgbw16();
+            assert_eq!([r, g, b, w].map(Color::narrow), [value; 4]);
+        }
+    }
+
+    #[test]
+    fn serial_command_is_lossless() {
+        let message = Message::from_serial(b"00000001ff7f0100\n", &StripConfig::COMPILE_ENV)
+            .expect("failed to decode serial command");
+        let expected = Message::Update(Command { strip: 0, pixel: 1, color: Color::Rgbw8([0xFF, 0x7F, 0x01, 0x00]) });
+        assert_eq!(message, expected);
+        assert_eq!(round_trip(message), expected);
+    }
+}
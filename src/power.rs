@@ -0,0 +1,158 @@
+//! The power model and budget limiter
+
+use crate::config::StripConfig;
+use crate::const_int_from_compileenv;
+
+/// The power budget of the strips, where all currents are in mA and a budget of `0` disables the limit
+///
+/// # Note
+/// The model assumes that the current of each channel is linear to its duty cycle. The quiescent current of the LEDs is
+/// not modelled, since it cannot be reduced by dimming anyway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerBudget {
+    /// The current of a single channel at full scale
+    pub channel_ma: u32,
+    /// The budget of each strip
+    pub strips: [u32; StripConfig::STRIPS],
+    /// The budget of all strips together
+    pub total: u32,
+}
+impl PowerBudget {
+    /// The power budget from the compile-time environment
+    pub const COMPILE_ENV: Self = {
+        /// The current of a single channel at full scale
+        const CHANNEL_MA: u32 = const_int_from_compileenv!("WS2812B_CHANNEL_MA" => u32, default: "20");
+        /// The budget of each strip
+        const STRIP_MA: u32 = const_int_from_compileenv!("WS2812B_STRIP_MA" => u32, default: "0");
+        /// The budget of all strips together
+        const TOTAL_MA: u32 = const_int_from_compileenv!("WS2812B_TOTAL_MA" => u32, default: "0");
+
+        Self { channel_ma: CHANNEL_MA, strips: [STRIP_MA; StripConfig::STRIPS], total: TOTAL_MA }
+    };
+
+    /// Sets the budget of the given strip, or the total budget if `strip` is `None`
+    ///
+    /// # Important
+    /// Strip indices that are out of range are silently ignored.
+    pub fn set(&mut self, strip: Option<usize>, budget: u32) {
+        let slot = match strip {
+            Some(strip) => self.strips.get_mut(strip),
+            None => Some(&mut self.total),
+        };
+        if let Some(slot) = slot {
+            *slot = budget;
+        }
+    }
+
+    /// Estimates the current of the given 16 bit RGBW pixels, where the white channel is only counted for RGBW strips
+    pub fn estimate<I>(&self, pixels: I, rgbw: bool) -> u32
+    where
+        I: IntoIterator<Item = [u16; 4]>,
+    {
+        let white = rgbw as u64;
+        let sum: u64 = pixels.into_iter().map(|[r, g, b, w]| r as u64 + g as u64 + b as u64 + (w as u64 * white)).sum();
+        Self::saturate((sum * self.channel_ma as u64).div_ceil(u16::MAX as u64))
+    }
+
+    /// Reduces the brightness levels of the strips so that their currents fit into the budgets, and returns the limited
+    /// levels and currents
+    ///
+    /// # Note
+    /// `currents` are the estimated currents of the strips at full brightness, and the current of a strip is assumed to
+    /// be linear to its brightness.
+    pub fn limit(
+        &self,
+        currents: [u32; StripConfig::STRIPS],
+        mut levels: [u8; StripConfig::STRIPS],
+    ) -> ([u8; StripConfig::STRIPS], [u32; StripConfig::STRIPS]) {
+        // Limit each strip to its own budget
+        for ((level, current), budget) in levels.iter_mut().zip(currents).zip(self.strips) {
+            if budget > 0 && Self::current(current, *level) > budget {
+                *level = ((budget as u64 * 255) / current as u64) as u8;
+            }
+        }
+
+        // Dim all strips uniformly until they fit into the total budget
+        let mut factor = u8::MAX;
+        let total = |factor: u8| -> u64 {
+            let currents = currents.iter().zip(levels);
+            currents.map(|(current, level)| Self::current(*current, Self::dim(level, factor)) as u64).sum()
+        };
+        while self.total > 0 && factor > 0 && total(factor) > self.total as u64 {
+            factor -= 1;
+        }
+
+        // Apply the factor
+        let levels = levels.map(|level| Self::dim(level, factor));
+        let currents = core::array::from_fn(|strip| Self::current(currents[strip], levels[strip]));
+        (levels, currents)
+    }
+
+    /// The current at the given brightness, which is rounded up to stay on the safe side
+    const fn current(current: u32, level: u8) -> u32 {
+        Self::saturate((current as u64 * level as u64).div_ceil(255))
+    }
+
+    /// Dims a brightness level by the given factor, which is rounded down to stay on the safe side
+    const fn dim(level: u8, factor: u8) -> u8 {
+        ((level as u16 * factor as u16) / 255) as u8
+    }
+
+    /// Saturates a current to 32 bit
+    const fn saturate(current: u64) -> u32 {
+        match current {
+            0..=0xFFFF_FFFF => current as u32,
+            _ => u32::MAX,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A budget without limits
+    const UNLIMITED: PowerBudget = PowerBudget { channel_ma: 20, strips: [0; StripConfig::STRIPS], total: 0 };
+
+    #[test]
+    fn full_white_draws_60ma_per_pixel() {
+        let pixels = [[u16::MAX; 4]; 100];
+        assert_eq!(UNLIMITED.estimate(pixels, false), 6000);
+        assert_eq!(UNLIMITED.estimate(pixels, true), 8000);
+        assert_eq!(UNLIMITED.estimate([[0x8000, 0, 0, 0]], false), 11);
+        assert_eq!(UNLIMITED.estimate([], true), 0);
+    }
+
+    #[test]
+    fn unlimited_budget_keeps_levels() {
+        let levels = core::array::from_fn(|strip| strip as u8 * 30);
+        let (limited, currents) = UNLIMITED.limit([u32::MAX; StripConfig::STRIPS], levels);
+        assert_eq!(limited, levels);
+        assert_eq!(currents[1], PowerBudget::current(u32::MAX, 30));
+    }
+
+    #[test]
+    fn strip_budget_is_enforced() {
+        let mut budget = UNLIMITED;
+        budget.set(Some(0), 1000);
+        budget.set(Some(StripConfig::STRIPS), 1);
+
+        let mut currents = [0; StripConfig::STRIPS];
+        (currents[0], currents[1]) = (6000, 6000);
+        let (levels, currents) = budget.limit(currents, [u8::MAX; StripConfig::STRIPS]);
+        assert_eq!((levels[0], currents[0]), (42, 989));
+        assert_eq!((levels[1], currents[1]), (255, 6000));
+    }
+
+    #[test]
+    fn total_budget_is_enforced() {
+        let mut budget = UNLIMITED;
+        budget.set(None, 2500);
+
+        for full in [2501, 6000, 100_000, u32::MAX] {
+            let (levels, currents) = budget.limit([full; StripConfig::STRIPS], [u8::MAX; StripConfig::STRIPS]);
+            assert!(currents.iter().sum::<u32>() <= 2500, "budget exceeded for {full} mA");
+            assert!(levels.iter().all(|level| *level == levels[0]), "strips are not dimmed uniformly");
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! A lock-free single-producer/single-consumer ring buffer for inter-core messages
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+/// A lock-free single-producer/single-consumer ring buffer of 32 bit words
+///
+/// # Important
+/// This ring buffer only requires atomic loads and stores, so it is also usable on targets without atomic
+/// read-modify-write operations (e.g. `thumbv6m`). `SIZE` must be a power of two.
+#[derive(Debug)]
+pub struct Ring<const SIZE: usize> {
+    /// The ring slots
+    slots: [UnsafeCell<u32>; SIZE],
+    /// The free-running write position, which is only modified by the producer
+    head: AtomicUsize,
+    /// The free-running read position, which is only modified by the consumer
+    tail: AtomicUsize,
+}
+impl<const SIZE: usize> Ring<SIZE> {
+    /// Creates a new empty ring buffer
+    pub const fn new() -> Self {
+        assert!(SIZE.is_power_of_two(), "ring size must be a power of two");
+        Self { slots: [const { UnsafeCell::new(0) }; SIZE], head: AtomicUsize::new(0), tail: AtomicUsize::new(0) }
+    }
+
+    /// Splits the ring buffer into the producer and consumer half
+    pub fn split(&mut self) -> (Producer<'_, SIZE>, Consumer<'_, SIZE>) {
+        (Producer { ring: self }, Consumer { ring: self })
+    }
+}
+impl<const SIZE: usize> Default for Ring<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+// Safety: Each slot is either owned by the producer or by the consumer, and ownership is transferred via the atomic
+// head and tail positions with acquire/release semantics
+unsafe impl<const SIZE: usize> Sync for Ring<SIZE> {}
+
+/// The producer half of a ring buffer
+#[derive(Debug)]
+pub struct Producer<'a, const SIZE: usize> {
+    /// The underlying ring buffer
+    ring: &'a Ring<SIZE>,
+}
+impl<const SIZE: usize> Producer<'_, SIZE> {
+    /// The amount of words that can be pushed without blocking
+    pub fn available(&self) -> usize {
+        let head = self.ring.head.load(Relaxed);
+        let tail = self.ring.tail.load(Acquire);
+        SIZE - head.wrapping_sub(tail)
+    }
+
+    /// Pushes all words at once if there is enough space available
+    ///
+    /// # Note
+    /// The words are published atomically, so the consumer never observes a partially written sequence of words.
+    pub fn push(&mut self, words: &[u32]) -> bool {
+        // Ensure that all words fit
+        if words.len() > self.available() {
+            return false;
+        }
+
+        // Write the words into the free slots
+        let head = self.ring.head.load(Relaxed);
+        for (offset, word) in words.iter().enumerate() {
+            let slot = &self.ring.slots[head.wrapping_add(offset) % SIZE];
+            // Safety: The slot is free and thus owned by the producer
+            unsafe { slot.get().write(*word) };
+        }
+
+        // Publish the words
+        self.ring.head.store(head.wrapping_add(words.len()), Release);
+        true
+    }
+}
+
+/// The consumer half of a ring buffer
+#[derive(Debug)]
+pub struct Consumer<'a, const SIZE: usize> {
+    /// The underlying ring buffer
+    ring: &'a Ring<SIZE>,
+}
+impl<const SIZE: usize> Consumer<'_, SIZE> {
+    /// Whether the ring buffer is empty
+    pub fn is_empty(&self) -> bool {
+        let head = self.ring.head.load(Acquire);
+        let tail = self.ring.tail.load(Relaxed);
+        head == tail
+    }
+
+    /// Pops the next word if available
+    pub fn pop(&mut self) -> Option<u32> {
+        // Ensure that there is a published word
+        if self.is_empty() {
+            return None;
+        }
+
+        // Read the word and release the slot
+        let tail = self.ring.tail.load(Relaxed);
+        // Safety: The slot has been published and is thus owned by the consumer
+        let word = unsafe { self.ring.slots[tail % SIZE].get().read() };
+        self.ring.tail.store(tail.wrapping_add(1), Release);
+        Some(word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn push_and_pop() {
+        let mut ring: Ring<4> = Ring::new();
+        let (mut producer, mut consumer) = ring.split();
+
+        // Fill the ring and ensure that an overflowing push is rejected as a whole
+        assert!(producer.push(&[1, 2, 3]));
+        assert!(!producer.push(&[4, 5]));
+        assert!(producer.push(&[4]));
+        assert_eq!(producer.available(), 0);
+
+        // Drain the ring and wrap around
+        assert_eq!([consumer.pop(), consumer.pop()], [Some(1), Some(2)]);
+        assert!(producer.push(&[5, 6]));
+        assert_eq!(
+            [consumer.pop(), consumer.pop(), consumer.pop(), consumer.pop()],
+            [Some(3), Some(4), Some(5), Some(6)]
+        );
+        assert_eq!(consumer.pop(), None);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn stress_two_threads() {
+        /// The amount of sequences to transfer
+        const SEQUENCES: u32 = 1_000_000;
+
+        let mut ring: Ring<64> = Ring::new();
+        let (mut producer, mut consumer) = ring.split();
+        thread::scope(|scope| {
+            // Push sequences of one to three words, where each word contains the sequence number and its offset
+            scope.spawn(move || {
+                for sequence in 0..SEQUENCES {
+                    let len = (sequence % 3) + 1;
+                    let words = [sequence << 2, (sequence << 2) | 1, (sequence << 2) | 2];
+                    while !producer.push(&words[..len as usize]) {
+                        thread::yield_now();
+                    }
+                }
+            });
+
+            // Pop all sequences and ensure that they are published atomically and in order
+            scope.spawn(move || {
+                for sequence in 0..SEQUENCES {
+                    // Wait for the first word
+                    let first = loop {
+                        match consumer.pop() {
+                            Some(word) => break word,
+                            None => thread::yield_now(),
+                        }
+                    };
+                    assert_eq!(first, sequence << 2);
+
+                    // The remaining words must be available immediately
+                    let len = (sequence % 3) + 1;
+                    for offset in 1..len {
+                        let word = consumer.pop().expect("sequence was not published atomically");
+                        assert_eq!(word, (sequence << 2) | offset);
+                    }
+                }
+                assert!(consumer.is_empty());
+            });
+        });
+    }
+}
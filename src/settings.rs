@@ -0,0 +1,404 @@
+//! The persistent driver settings and their flash store format
+
+use crate::config::{ColorOrder, StripConfig, Timing};
+use crate::const_int_from_compileenv;
+
+/// The animation that is shown on all strips after boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootAnimation {
+    /// All strips stay dark until the host sends the first update
+    Off,
+    /// All strips are filled with the given RGBW color
+    Fill([u8; 4]),
+}
+
+/// The USB identity of the driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsbIdentity {
+    /// The USB vendor ID
+    pub vid: u16,
+    /// The USB product ID
+    pub pid: u16,
+    /// The device ID for the USB serial number, or `0` to derive it from the flash chip
+    pub serial: u64,
+}
+impl UsbIdentity {
+    /// The default USB identity
+    pub const DEFAULT: Self = Self { vid: 0x16c0, pid: 0x27dd, serial: 0 };
+}
+
+/// The GPIO pin assignment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PinConfig {
+    /// The control pins for the strips driven by PIO 0
+    pub pio0: [u8; 4],
+    /// The control pins for the strips driven by PIO 1 (if enabled)
+    pub pio1: [u8; 4],
+    /// The status LED pin
+    pub led: u8,
+}
+impl PinConfig {
+    /// The pin assignment from the compile-time environment
+    pub const COMPILE_ENV: Self = {
+        /// The default PIO0 pins
+        #[cfg(not(feature = "seeduino-xiao"))]
+        const PIO0_DEFAULT: [&str; 4] = ["10", "11", "12", "13"];
+        /// The default PIO0 pins
+        #[cfg(feature = "seeduino-xiao")]
+        const PIO0_DEFAULT: [&str; 4] = ["0", "1", "2", "3"];
+        /// The default PIO1 pins
+        #[cfg(not(feature = "seeduino-xiao"))]
+        const PIO1_DEFAULT: [&str; 4] = ["14", "15", "16", "17"];
+        /// The default PIO1 pins
+        #[cfg(feature = "seeduino-xiao")]
+        const PIO1_DEFAULT: [&str; 4] = ["4", "5", "6", "7"];
+
+        /// PIO0 pin 0
+        const PIO0_PIN0: u8 = const_int_from_compileenv!("WS2812B_PIO0_PIN0" => u8, default: PIO0_DEFAULT[0]);
+        /// PIO0 pin 1
+        const PIO0_PIN1: u8 = const_int_from_compileenv!("WS2812B_PIO0_PIN1" => u8, default: PIO0_DEFAULT[1]);
+        /// PIO0 pin 2
+        const PIO0_PIN2: u8 = const_int_from_compileenv!("WS2812B_PIO0_PIN2" => u8, default: PIO0_DEFAULT[2]);
+        /// PIO0 pin 3
+        const PIO0_PIN3: u8 = const_int_from_compileenv!("WS2812B_PIO0_PIN3" => u8, default: PIO0_DEFAULT[3]);
+        /// PIO1 pin 0
+        const PIO1_PIN0: u8 = const_int_from_compileenv!("WS2812B_PIO1_PIN0" => u8, default: PIO1_DEFAULT[0]);
+        /// PIO1 pin 1
+        const PIO1_PIN1: u8 = const_int_from_compileenv!("WS2812B_PIO1_PIN1" => u8, default: PIO1_DEFAULT[1]);
+        /// PIO1 pin 2
+        const PIO1_PIN2: u8 = const_int_from_compileenv!("WS2812B_PIO1_PIN2" => u8, default: PIO1_DEFAULT[2]);
+        /// PIO1 pin 3
+        const PIO1_PIN3: u8 = const_int_from_compileenv!("WS2812B_PIO1_PIN3" => u8, default: PIO1_DEFAULT[3]);
+        /// The LED pin
+        const GPIO_LED: u8 = const_int_from_compileenv!("WS2812B_GPIO_LED" => u8, default: "25");
+
+        Self {
+            pio0: [PIO0_PIN0, PIO0_PIN1, PIO0_PIN2, PIO0_PIN3],
+            pio1: [PIO1_PIN0, PIO1_PIN1, PIO1_PIN2, PIO1_PIN3],
+            led: GPIO_LED,
+        }
+    };
+}
+
+/// The persistent driver settings
+///
+/// # Format
+/// The serial representation has a fixed size of 123 bytes (indices are byte offsets):
+///  - `[0, 96)`: Eight strip configurations with 12 bytes each: the big-endian 24 bit length, the flags (bit `0` marks
+///    an RGBW strip, bit `1` enables the strip), the color order and the 7 byte timing profile (see `Timing`); the
+///    configurations of strips that are not available are ignored
+///  - `[96, 100)`: The control pins for the strips driven by PIO 0
+///  - `[100, 104)`: The control pins for the strips driven by PIO 1
+///  - `[104]`: The status LED pin
+///  - `[105]`: The brightness limit
+///  - `[106, 108)`: The big-endian USB vendor ID
+///  - `[108, 110)`: The big-endian USB product ID
+///  - `[110, 118)`: The big-endian device ID for the USB serial number, or `0` to derive it from the flash chip
+///  - `[118]`: The boot animation; `0` (off) or `1` (fill)
+///  - `[119, 123)`: The RGBW color of the boot animation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// The strip configurations
+    pub strips: [StripConfig; StripConfig::STRIPS],
+    /// The GPIO pin assignment
+    pub pins: PinConfig,
+    /// The brightness limit
+    pub brightness_limit: u8,
+    /// The USB identity
+    pub usb: UsbIdentity,
+    /// The boot animation
+    pub boot: BootAnimation,
+}
+impl Settings {
+    /// The settings from the compile-time environment
+    pub const COMPILE_ENV: Self = Self {
+        strips: StripConfig::COMPILE_ENV,
+        pins: PinConfig::COMPILE_ENV,
+        brightness_limit: u8::MAX,
+        usb: UsbIdentity::DEFAULT,
+        boot: BootAnimation::Off,
+    };
+    /// The size of serialized settings
+    pub const SERIAL_LEN: usize = (Self::SERIAL_STRIPS * Self::SERIAL_STRIP_LEN) + 27;
+    /// The amount of serialized strip configurations
+    const SERIAL_STRIPS: usize = 8;
+    /// The size of a serialized strip configuration
+    const SERIAL_STRIP_LEN: usize = 5 + Timing::SERIAL_LEN;
+
+    /// Decodes serialized settings and validates them
+    pub fn from_serial(data: &[u8]) -> Option<Self> {
+        let data: &[u8; Self::SERIAL_LEN] = data.try_into().ok()?;
+        let (strips, rest) = data.split_at(Self::SERIAL_STRIPS * Self::SERIAL_STRIP_LEN);
+
+        // Decode the strip configurations
+        let mut configs = StripConfig::COMPILE_ENV;
+        for (config, strip) in configs.iter_mut().zip(strips.chunks_exact(Self::SERIAL_STRIP_LEN)) {
+            let strip: &[u8; Self::SERIAL_STRIP_LEN] = strip.try_into().ok()?;
+            let [len0, len1, len2, flags, order, timing @ ..] = *strip;
+            let len = u32::from_be_bytes([0, len0, len1, len2]) as usize;
+            let (rgbw, enabled) = (flags & 0b01 != 0, flags & 0b10 != 0);
+            let (order, timing) = (ColorOrder::from_u8(order)?, Timing::from_serial(timing)?);
+            *config = StripConfig { len, rgbw, order, timing, enabled };
+        }
+        if !StripConfig::fits_pool(&configs) {
+            return None;
+        }
+
+        // Decode the remaining fields
+        let boot = match (rest[22], &rest[23..27]) {
+            (0, _) => BootAnimation::Off,
+            (1, &[r, g, b, w]) => BootAnimation::Fill([r, g, b, w]),
+            _ => return None,
+        };
+        let usb = UsbIdentity {
+            vid: u16::from_be_bytes([rest[10], rest[11]]),
+            pid: u16::from_be_bytes([rest[12], rest[13]]),
+            serial: u64::from_be_bytes(rest[14..22].try_into().ok()?),
+        };
+        let pins = PinConfig { pio0: rest[0..4].try_into().ok()?, pio1: rest[4..8].try_into().ok()?, led: rest[8] };
+
+        // Init self
+        Some(Self { strips: configs, pins, brightness_limit: rest[9], usb, boot })
+    }
+
+    /// Encodes the settings into their serial representation
+    pub fn to_serial(&self) -> [u8; Self::SERIAL_LEN] {
+        let mut data = [0; Self::SERIAL_LEN];
+        let (strips, rest) = data.split_at_mut(Self::SERIAL_STRIPS * Self::SERIAL_STRIP_LEN);
+
+        // Encode the strip configurations
+        for (strip, config) in strips.chunks_exact_mut(Self::SERIAL_STRIP_LEN).zip(&self.strips) {
+            let StripConfig { len, rgbw, order, timing, enabled } = *config;
+            let [_, len0, len1, len2] = (len as u32).to_be_bytes();
+            let flags = ((enabled as u8) << 1) | (rgbw as u8);
+            strip[..5].copy_from_slice(&[len0, len1, len2, flags, order.to_u8()]);
+            strip[5..].copy_from_slice(&timing.to_serial());
+        }
+
+        // Encode the remaining fields
+        let (boot, rgbw) = match self.boot {
+            BootAnimation::Off => (0, [0; 4]),
+            BootAnimation::Fill(rgbw) => (1, rgbw),
+        };
+        rest[0..4].copy_from_slice(&self.pins.pio0);
+        rest[4..8].copy_from_slice(&self.pins.pio1);
+        rest[8..10].copy_from_slice(&[self.pins.led, self.brightness_limit]);
+        rest[10..12].copy_from_slice(&self.usb.vid.to_be_bytes());
+        rest[12..14].copy_from_slice(&self.usb.pid.to_be_bytes());
+        rest[14..22].copy_from_slice(&self.usb.serial.to_be_bytes());
+        rest[22] = boot;
+        rest[23..27].copy_from_slice(&rgbw);
+        data
+    }
+}
+
+/// A log-structured settings store within a reserved flash region
+///
+/// # Format
+/// The store consists of `SECTORS` erasable sectors, where each flash page may contain a settings record (indices are
+/// byte offsets):
+///  - `[0, 4)`: The magic bytes `WSCF`
+///  - `[4, 6)`: The big-endian format version
+///  - `[6, 10)`: The big-endian sequence number, which is incremented with every write
+///  - `[10, 133)`: The serialized settings
+///  - `[133, 137)`: The big-endian CRC-32 of all previous bytes
+///
+/// New records are appended to the page after the latest record, and a sector is only erased once the store wraps
+/// around into it. This spreads the erase cycles evenly over all sectors, and the latest record is never erased before
+/// its successor has been written.
+#[derive(Debug, Clone, Copy)]
+pub struct Store;
+impl Store {
+    /// The size of a flash page
+    pub const PAGE_SIZE: usize = 256;
+    /// The size of an erasable flash sector
+    pub const SECTOR_SIZE: usize = 4096;
+    /// The amount of sectors
+    pub const SECTORS: usize = 4;
+    /// The size of the store
+    pub const SIZE: usize = Self::SECTOR_SIZE * Self::SECTORS;
+    /// The record magic bytes
+    const MAGIC: [u8; 4] = *b"WSCF";
+    /// The record format version
+    const VERSION: u16 = 2;
+    /// The size of a record
+    const RECORD_LEN: usize = 10 + Settings::SERIAL_LEN + 4;
+
+    /// Loads the latest valid settings from the store
+    pub fn load(store: &[u8]) -> Option<Settings> {
+        Self::latest(store).map(|(_, _, settings)| settings)
+    }
+
+    /// Prepares the next write of the given settings
+    ///
+    /// # Returns
+    /// The offset of the page to program, the offset of the sector that must be erased before programming (if any), and
+    /// the page contents.
+    pub fn prepare(store: &[u8], settings: &Settings) -> (usize, Option<usize>, [u8; Self::PAGE_SIZE]) {
+        // Select the page after the latest record
+        let (page, sequence) = match Self::latest(store) {
+            Some((page, sequence, _)) => ((page + 1) % Self::pages(store), sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+
+        // Erase the sector if we wrap into it or if the page is not erased
+        let mut offset = page * Self::PAGE_SIZE;
+        let is_erased = store[offset..offset + Self::PAGE_SIZE].iter().all(|byte| *byte == 0xFF);
+        let erase = match (offset % Self::SECTOR_SIZE, is_erased) {
+            (0, _) => Some(offset),
+            (_, true) => None,
+            (_, false) => {
+                // Skip to the next sector since the latest record lives in the current sector
+                offset = (offset - (offset % Self::SECTOR_SIZE) + Self::SECTOR_SIZE) % store.len();
+                Some(offset)
+            }
+        };
+
+        // Encode the record
+        let mut record = [0xFF; Self::PAGE_SIZE];
+        record[0..4].copy_from_slice(&Self::MAGIC);
+        record[4..6].copy_from_slice(&Self::VERSION.to_be_bytes());
+        record[6..10].copy_from_slice(&sequence.to_be_bytes());
+        record[10..Self::RECORD_LEN - 4].copy_from_slice(&settings.to_serial());
+        let crc = crc32(&record[..Self::RECORD_LEN - 4]);
+        record[Self::RECORD_LEN - 4..Self::RECORD_LEN].copy_from_slice(&crc.to_be_bytes());
+        (offset, erase, record)
+    }
+
+    /// Finds the latest valid record and returns its page index, sequence number and settings
+    fn latest(store: &[u8]) -> Option<(usize, u32, Settings)> {
+        let mut latest: Option<(usize, u32, Settings)> = None;
+        for (page, record) in store.chunks_exact(Self::PAGE_SIZE).enumerate() {
+            // Decode the record
+            let Some((sequence, settings)) = Self::decode(record) else {
+                continue;
+            };
+
+            // Keep the record if it is newer
+            match latest {
+                Some((_, latest_sequence, _)) if latest_sequence >= sequence => (/* keep the latest record */),
+                _ => latest = Some((page, sequence, settings)),
+            }
+        }
+        latest
+    }
+
+    /// Decodes and validates a record
+    fn decode(record: &[u8]) -> Option<(u32, Settings)> {
+        // Validate the header and checksum
+        let record = record.get(..Self::RECORD_LEN)?;
+        let (data, crc) = record.split_at(Self::RECORD_LEN - 4);
+        if data[0..4] != Self::MAGIC || data[4..6] != Self::VERSION.to_be_bytes() {
+            return None;
+        }
+        if crc32(data).to_be_bytes() != crc {
+            return None;
+        }
+
+        // Decode the record
+        let sequence = u32::from_be_bytes([data[6], data[7], data[8], data[9]]);
+        let settings = Settings::from_serial(&data[10..])?;
+        Some((sequence, settings))
+    }
+
+    /// The amount of pages within the store
+    fn pages(store: &[u8]) -> usize {
+        store.len() / Self::PAGE_SIZE
+    }
+}
+
+/// Computes the CRC-32 (IEEE 802.3) of the given data
+pub const fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    let mut index = 0;
+    while index < data.len() {
+        crc ^= data[index] as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB8_8320,
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        index += 1;
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates some non-default settings
+    fn settings(seed: u8) -> Settings {
+        let mut settings = Settings::COMPILE_ENV;
+        settings.strips[1] = StripConfig { rgbw: true, enabled: false, ..StripConfig::new(100 + seed as usize, false) };
+        settings.strips[2].timing = Timing::Custom { t0h: 300, t1h: 900 + seed as u16, tl: 400 };
+        settings.pins.led = seed;
+        settings.brightness_limit = 128;
+        settings.usb = UsbIdentity { vid: 0x1209, pid: 0x0001, serial: 0x0123_4567_89AB_CDEF };
+        settings.boot = BootAnimation::Fill([seed, 2, 3, 4]);
+        settings
+    }
+
+    /// Applies a prepared write to the store like the flash hardware does
+    fn write(store: &mut [u8], settings: &Settings) {
+        let (offset, erase, page) = Store::prepare(store, settings);
+        if let Some(erase) = erase {
+            store[erase..erase + Store::SECTOR_SIZE].fill(0xFF);
+        }
+        // Note: Programming can only clear bits
+        for (target, source) in store[offset..offset + Store::PAGE_SIZE].iter_mut().zip(page) {
+            *target &= source;
+        }
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn settings_are_lossless() {
+        let settings = settings(7);
+        assert_eq!(Settings::from_serial(&settings.to_serial()), Some(settings));
+        assert_eq!(Settings::from_serial(&Settings::COMPILE_ENV.to_serial()), Some(Settings::COMPILE_ENV));
+    }
+
+    #[test]
+    fn empty_store_has_no_settings() {
+        let store = [0xFF; Store::SIZE];
+        assert_eq!(Store::load(&store), None);
+    }
+
+    #[test]
+    fn corrupt_record_is_ignored() {
+        let mut store = [0xFF; Store::SIZE];
+        write(&mut store, &settings(1));
+        write(&mut store, &settings(2));
+
+        // Flip a bit in the latest record, so that the previous record becomes the latest one
+        store[Store::PAGE_SIZE + 20] ^= 0x01;
+        assert_eq!(Store::load(&store), Some(settings(1)));
+    }
+
+    #[test]
+    fn writes_rotate_through_all_sectors() {
+        let mut store = [0xFF; Store::SIZE];
+        let mut erases = [0; Store::SECTORS];
+        for round in 0..(3 * Store::SIZE / Store::PAGE_SIZE) {
+            // Track the erased sectors
+            let settings = settings(round as u8);
+            let (_, erase, _) = Store::prepare(&store, &settings);
+            if let Some(erase) = erase {
+                erases[erase / Store::SECTOR_SIZE] += 1;
+            }
+
+            // Write the record and ensure that it is the latest one
+            write(&mut store, &settings);
+            assert_eq!(Store::load(&store), Some(settings));
+        }
+        assert_eq!(erases, [3; Store::SECTORS]);
+    }
+}
@@ -1,43 +1,568 @@
 //! A main task that reads update commands from the serial interface and applies them
 
-use crate::board::hal::sio::SioFifo;
 use crate::board::hal::usb::UsbBus;
-use crate::command::Command;
+use crate::hardware::flash;
 use crate::hardware::usb::UsbSerialDevice;
 use crate::strbuffer::StrBuffer;
+use crate::ws2812b::{self, Core1Sender};
+use ws2812b_driver::ack::AckMode;
+use ws2812b_driver::ambilight::{AmbilightHeader, Fletcher};
+use ws2812b_driver::command::{Color, Command, DecodeError};
+use ws2812b_driver::config::StripConfig;
+use ws2812b_driver::format::{Format, FormatDetector};
+use ws2812b_driver::frame::{
+    AckSetup, Control, FrameHeader, FramingReport, GammaUpload, PixelBatch, PowerReport, SettingsFrame, StripSetup,
+    UniverseData, UniverseSetup,
+};
+use ws2812b_driver::gamma::Curve;
+use ws2812b_driver::line::LineBuffer;
+use ws2812b_driver::message::Message;
+use ws2812b_driver::settings::{BootAnimation, Settings, Store};
+use ws2812b_driver::tpm2::{Tpm2Header, Tpm2Kind};
+use ws2812b_driver::universe::UniverseMap;
+
+/// The configurations of all strips
+type StripConfigs = [StripConfig; StripConfig::STRIPS];
+
+/// The result of processing a binary, ambilight or TPM2 frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameStatus {
+    /// The frame was applied or answered
+    Accepted,
+    /// The frame was rejected, but it has been consumed entirely
+    Dropped,
+    /// The frame header is corrupted, so the following bytes must be discarded until the stream is in sync again
+    Corrupted,
+}
+impl FrameStatus {
+    /// Counts a rejected frame and returns whether the stream is still in sync
+    fn count(self, errors: &mut FramingReport) -> bool {
+        if self != Self::Accepted {
+            errors.malformed = errors.malformed.saturating_add(1);
+        }
+        self != Self::Corrupted
+    }
+}
 
 /// A main task that reads update commands from the serial interface and applies them
-pub async fn task(usb_bus: UsbBus, serno: StrBuffer<64>, sio_fifo: &mut SioFifo) {
+pub async fn task(usb_bus: UsbBus, serno: StrBuffer<64>, settings: Settings, core1: &mut Core1Sender) {
+    // Show the boot animation
+    let mut configs = settings.strips;
+    let mut universes = UniverseMap::new();
+    let mut errors = FramingReport::default();
+    show_boot_animation(&settings, core1).await;
+
     // Read incoming commands and forward them to the second core
-    let mut serial = UsbSerialDevice::new(usb_bus, serno);
-    'message_loop: loop {
-        // Try to read the next command line
-        let mut buf = [0; Command::SERIAL_LEN];
-        serial.read_until(&mut buf, |buf| buf.ends_with(b"\n")).await;
-
-        // Check for bootsel message, reset if appropriate
-        #[cfg(feature = "bootsel")]
-        if buf == *b"RESET_TO_BOOTSEL\n" {
-            // Disconnect USB and reset the pico
-            serial.try_reset();
-            crate::board::hal::rom_data::reset_to_usb_boot(0, 0);
-        }
-
-        // Parse the update or drop the message if the update is invalid
-        let Some(update) = Command::from_serial(&buf) else {
-            // Restart the message loop
-            continue 'message_loop;
+    let mut serial = UsbSerialDevice::new(usb_bus, serno, &settings.usb);
+    let mut in_sync = true;
+    loop {
+        // Read the first bytes to detect the message format, or discard garbage after a corrupted frame
+        let mut detector = match in_sync {
+            true => FormatDetector::new(),
+            false => FormatDetector::resync(),
+        };
+        let format = loop {
+            let mut next = [0; 1];
+            serial.read_exact(&mut next).await;
+            if let Some(format) = detector.push(next[0]) {
+                break format;
+            }
+        };
+
+        // Process the message
+        let prefix = detector.prefix();
+        in_sync = match format {
+            Format::Frame => process_frame(&mut serial, &mut configs, &mut universes, &mut errors, core1).await,
+            Format::Ambilight => process_ambilight(prefix, &mut serial, &configs, &mut errors, core1).await,
+            Format::Tpm2 => process_tpm2(&mut serial, &configs, &mut errors, core1).await,
+            Format::Line => {
+                process_line(prefix, &mut serial, &configs, &mut errors, core1).await;
+                true
+            }
+        };
+    }
+}
+
+/// Fills all enabled strips with the color of the boot animation
+async fn show_boot_animation(settings: &Settings, core1: &mut Core1Sender) {
+    let BootAnimation::Fill([red, green, blue, white]) = settings.boot else {
+        return;
+    };
+
+    // Fill all pixels of all strips, where the white value is only applied to RGBW strips
+    for (strip, config) in settings.strips.iter().enumerate() {
+        let white = match config.rgbw {
+            true => white,
+            false => 0,
+        };
+        for pixel in 0..config.len {
+            // Skip the strip if it does not accept updates
+            let color = Color::Rgbw8([red, green, blue, white]);
+            let Some(update) = Command::new(strip, pixel, color, &settings.strips) else {
+                break;
+            };
+            core1.send(Message::Update(update)).await;
+        }
+    }
+    core1.send(Message::Show).await;
+}
+
+/// Reads the remainder of a command line and applies it
+async fn process_line(
+    prefix: &[u8],
+    serial: &mut UsbSerialDevice,
+    configs: &StripConfigs,
+    errors: &mut FramingReport,
+    core1: &mut Core1Sender,
+) {
+    // Read the remainder of the command line
+    let mut line = LineBuffer::new();
+    if !line.extend(prefix) {
+        serial.read_line(&mut line).await;
+    }
+
+    // Discard overlong lines and reply with the error and the beginning of the line
+    let Some(line) = line.line() else {
+        errors.overlong = errors.overlong.saturating_add(1);
+        serial.write_all(&DecodeError::Overlong.to_serial()).await;
+        serial.write_all(line.head()).await;
+        serial.write_all(b"\n").await;
+        return;
+    };
+
+    // Check for bootsel message, reset if appropriate
+    #[cfg(feature = "bootsel")]
+    if line == b"RESET_TO_BOOTSEL\n" {
+        // Disconnect USB and reset the pico
+        serial.try_reset();
+        crate::board::hal::rom_data::reset_to_usb_boot(0, 0);
+    }
+
+    // Acknowledge an acknowledgement mode command with the previous mode before switching to the new mode
+    if let Some(mode) = AckMode::from_serial(line) {
+        serial.acknowledge(line).await;
+        serial.set_ack_mode(mode);
+        return;
+    }
+
+    // Parse the message or reply with the error and the rejected command line if it is invalid
+    let message = match Message::from_serial(line, configs) {
+        Ok(message) => message,
+        Err(error) => {
+            serial.write_all(&error.to_serial()).await;
+            serial.write_all(line).await;
+            return;
+        }
+    };
+
+    // Send the message to the other core and acknowledge the command line to indicate success
+    core1.send(message).await;
+    serial.acknowledge(line).await;
+}
+
+/// Reads the remainder of an Adalight or AWA frame and maps its pixels onto the enabled strips, and returns whether the
+/// stream is still in sync
+async fn process_ambilight(
+    prefix: &[u8],
+    serial: &mut UsbSerialDevice,
+    configs: &StripConfigs,
+    errors: &mut FramingReport,
+    core1: &mut Core1Sender,
+) -> bool {
+    // Read the remainder of the header or drop the frame if it is invalid
+    let mut header = [0; AmbilightHeader::SERIAL_LEN];
+    header[..prefix.len()].copy_from_slice(prefix);
+    serial.read_exact(&mut header[prefix.len()..]).await;
+    let Some(header) = AmbilightHeader::from_serial(&header) else {
+        return FrameStatus::Corrupted.count(errors);
+    };
+
+    // Read and apply the pixels one by one to avoid buffering the entire frame
+    let mut fletcher = Fletcher::new();
+    for index in 0..header.count {
+        // Read the next pixel
+        let mut rgb = [0; 3];
+        serial.read_exact(&mut rgb).await;
+        fletcher.update(&rgb);
+
+        // Map the pixel onto the strips or drop it if it exceeds the strips
+        let Some(update) = Command::linear(index, rgb, configs) else {
+            continue;
+        };
+        core1.send(Message::Update(update)).await;
+    }
+
+    // Validate the checksum if any and show the frame
+    // Note: Ambilight clients don't expect a reply
+    let mut trailer = [0; AmbilightHeader::TRAILER_LEN_MAX];
+    let trailer = &mut trailer[..header.trailer_len()];
+    serial.read_exact(trailer).await;
+    if !trailer.is_empty() && !fletcher.verify(trailer) {
+        return FrameStatus::Dropped.count(errors);
+    }
+    core1.send(Message::Show).await;
+    true
+}
+
+/// Reads the remainder of a TPM2 packet and applies it, and returns whether the stream is still in sync
+async fn process_tpm2(
+    serial: &mut UsbSerialDevice,
+    configs: &StripConfigs,
+    errors: &mut FramingReport,
+    core1: &mut Core1Sender,
+) -> bool {
+    // Read the remainder of the header or drop the packet if it is invalid
+    let mut header = [Tpm2Header::MAGIC; Tpm2Header::SERIAL_LEN];
+    serial.read_exact(&mut header[1..]).await;
+    let Some(header) = Tpm2Header::from_serial(&header) else {
+        return FrameStatus::Corrupted.count(errors);
+    };
+
+    // Process the payload
+    let message = match header.kind {
+        Tpm2Kind::Data => {
+            // Read and apply the pixels one by one to avoid buffering the entire frame
+            for index in 0..(header.len / 3) {
+                // Read the next pixel
+                let mut rgb = [0; 3];
+                serial.read_exact(&mut rgb).await;
+
+                // Map the pixel onto the strips or drop it if it exceeds the strips
+                let Some(update) = Command::linear(index, rgb, configs) else {
+                    continue;
+                };
+                core1.send(Message::Update(update)).await;
+            }
+
+            // Skip incomplete pixels
+            serial.skip(header.len % 3).await;
+            Some(Message::Show)
+        }
+        Tpm2Kind::Command if header.len <= Tpm2Header::COMMAND_LEN_MAX => {
+            // Read the command
+            let mut payload = [0; Tpm2Header::COMMAND_LEN_MAX];
+            serial.read_exact(&mut payload[..header.len]).await;
+            Tpm2Header::command(&payload[..header.len])
+        }
+        Tpm2Kind::Command | Tpm2Kind::Response => {
+            // Resynchronize instead of skipping unsupported packets, since their length cannot be validated
+            // Note: Response packets are only sent by devices
+            return FrameStatus::Corrupted.count(errors);
+        }
+    };
+
+    // Apply the message if the packet is terminated correctly
+    // Note: TPM2 clients don't expect a reply
+    let mut end = [0; 1];
+    serial.read_exact(&mut end).await;
+    match (message, end) {
+        (Some(message), [Tpm2Header::END]) => core1.send(message).await,
+        (None, [Tpm2Header::END]) => (/* drop unsupported commands */),
+        _ => return FrameStatus::Corrupted.count(errors),
+    }
+    true
+}
+
+/// Reads the remainder of a binary frame and applies it, and returns whether the stream is still in sync
+async fn process_frame(
+    serial: &mut UsbSerialDevice,
+    configs: &mut StripConfigs,
+    universes: &mut UniverseMap,
+    errors: &mut FramingReport,
+    core1: &mut Core1Sender,
+) -> bool {
+    // Read the remainder of the frame header
+    let mut header = [FrameHeader::MAGIC; FrameHeader::SERIAL_LEN];
+    serial.read_exact(&mut header[1..]).await;
+    let header = FrameHeader::from_serial(&header).expect("invalid frame header");
+
+    // Treat the header as corrupted if the frame kind is unknown or the length exceeds the frame kind
+    let status = match FrameHeader::len_max(header.kind) {
+        Some(len_max) if header.len <= len_max => match header.kind {
+            PixelBatch::KIND => process_batch(header, serial, configs, core1).await,
+            StripSetup::KIND_SET | StripSetup::KIND_GET => process_setup(header, serial, configs, core1).await,
+            SettingsFrame::KIND_GET | SettingsFrame::KIND_SET | SettingsFrame::KIND_RESET => {
+                process_settings(header, serial, core1).await
+            }
+            GammaUpload::KIND => process_gamma(header, serial, core1).await,
+            PowerReport::KIND => process_power(header, serial).await,
+            UniverseData::KIND => process_universe(header, serial, configs, universes, core1).await,
+            UniverseSetup::KIND_SET | UniverseSetup::KIND_GET => process_mapping(header, serial, universes).await,
+            AckSetup::KIND => process_ack(header, serial).await,
+            FramingReport::KIND => process_errors(header, serial, errors).await,
+            _ => process_control(header, serial, core1).await,
+        },
+        _ => FrameStatus::Corrupted,
+    };
+    status.count(errors)
+}
+
+/// Reads the body of an acknowledgement mode frame and applies it
+async fn process_ack(header: FrameHeader, serial: &mut UsbSerialDevice) -> FrameStatus {
+    // Read the body
+    let mut body = [0; AckSetup::SERIAL_LEN_MAX];
+    serial.read_exact(&mut body[..header.len]).await;
+
+    // Parse the mode or drop it if it is invalid
+    let Some(mode) = AckMode::from_frame(&body[..header.len]) else {
+        return FrameStatus::Dropped;
+    };
+
+    // Acknowledge the frame with the previous mode before switching to the new mode
+    serial.acknowledge(&header.to_serial()).await;
+    serial.set_ack_mode(mode);
+    FrameStatus::Accepted
+}
+
+/// Reads the body of a universe data frame and applies it
+async fn process_universe(
+    header: FrameHeader,
+    serial: &mut UsbSerialDevice,
+    configs: &StripConfigs,
+    universes: &UniverseMap,
+    core1: &mut Core1Sender,
+) -> FrameStatus {
+    // Read the body
+    let mut body = [0; UniverseData::SERIAL_LEN_MAX];
+    serial.read_exact(&mut body[..header.len]).await;
+
+    // Parse the universe or drop it if it is invalid
+    let Some((universe, channels)) = UniverseData::from_serial(&body[..header.len]) else {
+        return FrameStatus::Dropped;
+    };
+
+    // Send the mapped updates to the other core and acknowledge the frame to indicate success
+    for update in universes.commands(universe, channels, configs) {
+        core1.send(Message::Update(update)).await;
+    }
+    serial.acknowledge(&header.to_serial()).await;
+    FrameStatus::Accepted
+}
+
+/// Reads the body of a universe mapping frame and applies or answers it
+async fn process_mapping(
+    header: FrameHeader,
+    serial: &mut UsbSerialDevice,
+    universes: &mut UniverseMap,
+) -> FrameStatus {
+    // Read the body
+    let mut body = [0; UniverseSetup::SERIAL_LEN];
+    serial.read_exact(&mut body[..header.len]).await;
+
+    // Answer a query with the current mapping of the slot
+    if let (UniverseSetup::KIND_GET, &[slot]) = (header.kind, &body[..header.len]) {
+        let Some(mapping) = universes.mappings.get(slot as usize) else {
+            return FrameStatus::Dropped;
         };
+        let setup = UniverseSetup { slot: slot as usize, mapping: *mapping };
+        let header = FrameHeader { kind: UniverseSetup::KIND_SET, len: UniverseSetup::SERIAL_LEN };
+        serial.write_all(&header.to_serial()).await;
+        serial.write_all(&setup.to_serial()).await;
+        return FrameStatus::Accepted;
+    }
+
+    // Parse the mapping or drop it if it is invalid
+    let setup = UniverseSetup::from_serial(&body[..header.len]);
+    let (UniverseSetup::KIND_SET, Some(UniverseSetup { slot, mapping })) = (header.kind, setup) else {
+        return FrameStatus::Dropped;
+    };
+
+    // Apply the mapping and acknowledge the frame to indicate success
+    universes.mappings[slot] = mapping;
+    serial.acknowledge(&header.to_serial()).await;
+    FrameStatus::Accepted
+}
+
+/// Reads the body of a pixel batch frame and applies it
+async fn process_batch(
+    header: FrameHeader,
+    serial: &mut UsbSerialDevice,
+    configs: &StripConfigs,
+    core1: &mut Core1Sender,
+) -> FrameStatus {
+    // Read and validate the batch header, or reject the entire frame with the error and the frame header
+    let mut batch = [0; PixelBatch::SERIAL_LEN];
+    let batch_len = core::cmp::min(header.len, batch.len());
+    serial.read_exact(&mut batch[..batch_len]).await;
+    let batch = match PixelBatch::from_serial(&header, &batch, configs) {
+        Ok(batch) => batch,
+        Err(error) => {
+            serial.write_all(&error.to_serial()).await;
+            serial.write_all(&header.to_serial()).await;
 
-        // Wait until the SIO FIFO has some available space
-        while !sio_fifo.is_write_ready() {
-            // Always yield here to avoid a tight loop
-            embedded_runtime_rp2040::spin_once().await;
+            // Skip the pixels if the frame length is valid, otherwise the header is corrupted
+            if error == DecodeError::InvalidFrame {
+                return FrameStatus::Corrupted;
+            }
+            serial.skip(header.len - batch_len).await;
+            return FrameStatus::Dropped;
         }
+    };
+
+    // Read and apply the pixels one by one to avoid buffering the entire frame
+    for offset in 0..batch.count {
+        let mut pixel = [0; 8];
+        serial.read_exact(&mut pixel[..batch.channels]).await;
+        let update = batch.command(offset, &pixel, configs).expect("invalid pixel in validated batch");
+        core1.send(Message::Update(update)).await;
+    }
+
+    // Acknowledge the frame once to indicate success
+    serial.acknowledge(&header.to_serial()).await;
+    FrameStatus::Accepted
+}
 
-        // Send the update to the other core and reflect the message to indicate success
-        let update = update.to_u32();
-        sio_fifo.write(update);
-        serial.write_all(&buf).await;
+/// Reads the body of a custom gamma table frame and applies it
+async fn process_gamma(header: FrameHeader, serial: &mut UsbSerialDevice, core1: &mut Core1Sender) -> FrameStatus {
+    // Drop the entire frame if it has an invalid size
+    if header.len != GammaUpload::SERIAL_LEN {
+        serial.skip(header.len).await;
+        return FrameStatus::Dropped;
+    }
+
+    // Read and apply the entries one by one to avoid buffering the entire table
+    for index in 0..(GammaUpload::SERIAL_LEN / 2) {
+        let mut entry = [0; 2];
+        serial.read_exact(&mut entry).await;
+        let message = GammaUpload::entry(index, &entry).expect("invalid gamma table entry");
+        core1.send(message).await;
+    }
+
+    // Select the uploaded table and acknowledge the frame to indicate success
+    core1.send(Message::SetCurve(Curve::Custom)).await;
+    serial.acknowledge(&header.to_serial()).await;
+    FrameStatus::Accepted
+}
+
+/// Answers a power report frame
+async fn process_power(_header: FrameHeader, serial: &mut UsbSerialDevice) -> FrameStatus {
+    // Answer with the estimated currents of the last refresh
+    // Note: The frame has no body since the frame length has been validated against the frame kind
+    let report = PowerReport { strips: ws2812b::currents() };
+    let header = FrameHeader { kind: PowerReport::KIND, len: PowerReport::SERIAL_LEN };
+    serial.write_all(&header.to_serial()).await;
+    serial.write_all(&report.to_serial()).await;
+    FrameStatus::Accepted
+}
+
+/// Answers a framing error report frame
+async fn process_errors(_header: FrameHeader, serial: &mut UsbSerialDevice, errors: &FramingReport) -> FrameStatus {
+    // Answer with the framing error counters
+    // Note: The frame has no body since the frame length has been validated against the frame kind
+    let header = FrameHeader { kind: FramingReport::KIND, len: FramingReport::SERIAL_LEN };
+    serial.write_all(&header.to_serial()).await;
+    serial.write_all(&errors.to_serial()).await;
+    FrameStatus::Accepted
+}
+
+/// Reads the body of a control frame and applies it
+async fn process_control(header: FrameHeader, serial: &mut UsbSerialDevice, core1: &mut Core1Sender) -> FrameStatus {
+    // Read the body
+    let mut body = [0; Control::SERIAL_LEN];
+    serial.read_exact(&mut body[..header.len]).await;
+
+    // Parse the message or drop it if it is invalid
+    let Some(message) = Control::from_serial(&header, &body[..header.len]) else {
+        return FrameStatus::Dropped;
+    };
+
+    // Send the message to the other core and acknowledge the frame to indicate success
+    core1.send(message).await;
+    serial.acknowledge(&header.to_serial()).await;
+    FrameStatus::Accepted
+}
+
+/// Reads the body of a strip configuration frame and applies or answers it
+async fn process_setup(
+    header: FrameHeader,
+    serial: &mut UsbSerialDevice,
+    configs: &mut StripConfigs,
+    core1: &mut Core1Sender,
+) -> FrameStatus {
+    // Read the body
+    let mut body = [0; StripSetup::SERIAL_LEN];
+    serial.read_exact(&mut body[..header.len]).await;
+
+    // Answer a query with the current configuration of the strip
+    if let (StripSetup::KIND_GET, &[strip]) = (header.kind, &body[..header.len]) {
+        let Some(config) = configs.get(strip as usize) else {
+            return FrameStatus::Dropped;
+        };
+        let setup = StripSetup { strip: strip as usize, config: *config };
+        let header = FrameHeader { kind: StripSetup::KIND_SET, len: StripSetup::SERIAL_LEN };
+        serial.write_all(&header.to_serial()).await;
+        serial.write_all(&setup.to_serial()).await;
+        return FrameStatus::Accepted;
+    }
+
+    // Parse the configuration or drop it if it is invalid
+    let setup = StripSetup::from_serial(&body[..header.len]);
+    let (StripSetup::KIND_SET, Some(StripSetup { strip, config })) = (header.kind, setup) else {
+        return FrameStatus::Dropped;
+    };
+    if !StripConfig::reconfigure(configs, strip, config) {
+        return FrameStatus::Dropped;
+    }
+
+    // Send the configuration to the other core and acknowledge the frame to indicate success
+    core1.send(Message::Configure(strip, config)).await;
+    serial.acknowledge(&header.to_serial()).await;
+    FrameStatus::Accepted
+}
+
+/// Reads the body of a persistent settings frame and applies or answers it
+async fn process_settings(header: FrameHeader, serial: &mut UsbSerialDevice, core1: &mut Core1Sender) -> FrameStatus {
+    // Read the body
+    let mut body = [0; Settings::SERIAL_LEN];
+    serial.read_exact(&mut body[..header.len]).await;
+
+    // Process the frame
+    match (header.kind, header.len) {
+        (SettingsFrame::KIND_GET, 0) => {
+            // Answer with the stored settings
+            let settings = Store::load(flash::store()).unwrap_or(Settings::COMPILE_ENV);
+            let header = FrameHeader { kind: SettingsFrame::KIND_SET, len: Settings::SERIAL_LEN };
+            serial.write_all(&header.to_serial()).await;
+            serial.write_all(&settings.to_serial()).await;
+            FrameStatus::Accepted
+        }
+        (SettingsFrame::KIND_SET, Settings::SERIAL_LEN) => {
+            // Parse the settings or drop them if they are invalid
+            let Some(settings) = Settings::from_serial(&body) else {
+                return FrameStatus::Dropped;
+            };
+
+            // Append the settings to the store
+            let (offset, erase, page) = Store::prepare(flash::store(), &settings);
+            core1
+                .with_core1_parked(|| {
+                    // Safety: Core 1 is parked in RAM and interrupts are disabled within the critical section
+                    critical_section::with(|_| unsafe {
+                        if let Some(erase) = erase {
+                            flash::erase(erase);
+                        }
+                        flash::program(offset, &page);
+                    })
+                })
+                .await;
+            serial.acknowledge(&header.to_serial()).await;
+            FrameStatus::Accepted
+        }
+        (SettingsFrame::KIND_RESET, 0) => {
+            // Erase the entire store
+            core1
+                .with_core1_parked(|| {
+                    // Safety: Core 1 is parked in RAM and interrupts are disabled within the critical section
+                    critical_section::with(|_| unsafe {
+                        for offset in (0..Store::SIZE).step_by(Store::SECTOR_SIZE) {
+                            flash::erase(offset);
+                        }
+                    })
+                })
+                .await;
+            serial.acknowledge(&header.to_serial()).await;
+            FrameStatus::Accepted
+        }
+        _ => FrameStatus::Dropped,
     }
 }
@@ -0,0 +1,95 @@
+//! The TPM2 serial protocol
+
+use crate::message::Message;
+
+/// The type of a TPM2 packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tpm2Kind {
+    /// A data packet with the RGB values of the LEDs
+    Data,
+    /// A command packet
+    Command,
+    /// A response packet, which is only sent by devices
+    Response,
+}
+
+/// The header of a TPM2 packet
+///
+/// # Format
+/// Each packet starts with a 4 byte header (indices are byte offsets), followed by the payload and the end byte `0x36`:
+///  - `[0]`: The start byte `0xC9`
+///  - `[1]`: The packet type; `0xDA` (data), `0xC0` (command) or `0xAA` (response)
+///  - `[2, 4)`: The big-endian length of the payload in bytes
+///
+/// The payload of a data packet consists of the RGB values of the LEDs. The payload of a command packet consists of the
+/// control byte, the command and its arguments (see `Tpm2Header::command`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tpm2Header {
+    /// The packet type
+    pub kind: Tpm2Kind,
+    /// The length of the payload
+    pub len: usize,
+}
+impl Tpm2Header {
+    /// The start byte of a TPM2 packet
+    pub const MAGIC: u8 = 0xC9;
+    /// The end byte of a TPM2 packet
+    pub const END: u8 = 0x36;
+    /// The size of a serial TPM2 header
+    pub const SERIAL_LEN: usize = 4;
+    /// The maximum size of a supported command payload
+    pub const COMMAND_LEN_MAX: usize = 3;
+    /// The command to set the master brightness
+    const COMMAND_BRIGHTNESS: u8 = 0x0A;
+
+    /// Decodes a serial TPM2 header
+    pub fn from_serial(data: &[u8]) -> Option<Self> {
+        let [Self::MAGIC, kind, len_high, len_low] = *data else {
+            return None;
+        };
+        let kind = match kind {
+            0xDA => Tpm2Kind::Data,
+            0xC0 => Tpm2Kind::Command,
+            0xAA => Tpm2Kind::Response,
+            _ => return None,
+        };
+        Some(Self { kind, len: u16::from_be_bytes([len_high, len_low]) as usize })
+    }
+
+    /// Decodes a command payload into the associated inter-core message
+    ///
+    /// # Note
+    /// Only the master brightness command `0x0A` is supported, and the control byte is ignored since the driver doesn't
+    /// send responses.
+    pub fn command(payload: &[u8]) -> Option<Message> {
+        match payload {
+            [_control, Self::COMMAND_BRIGHTNESS, level] => Some(Message::SetBrightness(None, *level)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_are_decoded() {
+        let data = Tpm2Header::from_serial(&[0xC9, 0xDA, 0x03, 0x84]).expect("failed to decode data header");
+        assert_eq!(data, Tpm2Header { kind: Tpm2Kind::Data, len: 900 });
+
+        let command = Tpm2Header::from_serial(&[0xC9, 0xC0, 0x00, 0x03]).expect("failed to decode command header");
+        assert_eq!(command, Tpm2Header { kind: Tpm2Kind::Command, len: 3 });
+
+        assert_eq!(Tpm2Header::from_serial(&[0xC9, 0xDB, 0x00, 0x03]), None);
+        assert_eq!(Tpm2Header::from_serial(&[0x9C, 0xDA, 0x00, 0x03]), None);
+    }
+
+    #[test]
+    fn commands_are_decoded() {
+        assert_eq!(Tpm2Header::command(&[0x00, 0x0A, 0x80]), Some(Message::SetBrightness(None, 0x80)));
+        assert_eq!(Tpm2Header::command(&[0x80, 0x0A, 0xFF]), Some(Message::SetBrightness(None, 0xFF)));
+        assert_eq!(Tpm2Header::command(&[0x00, 0x0B, 0x80]), None);
+        assert_eq!(Tpm2Header::command(&[0x00, 0x0A]), None);
+    }
+}
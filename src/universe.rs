@@ -0,0 +1,137 @@
+//! The DMX512 universe mapping
+
+use crate::command::{Color, Command};
+use crate::config::StripConfig;
+
+/// A mapping of a channel range within a DMX512 universe onto a pixel range within a strip
+///
+/// # Note
+/// Each pixel occupies 3 consecutive channels (RGB), or 4 channels (RGBW) if the strip is an RGBW strip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniverseMapping {
+    /// The universe
+    pub universe: u16,
+    /// The first channel within the universe (zero-based)
+    pub channel: u16,
+    /// The index of the LED strip
+    pub strip: usize,
+    /// The index of the first pixel within the strip
+    pub pixel: usize,
+    /// The amount of pixels
+    pub count: usize,
+}
+impl UniverseMapping {
+    /// Creates the update for the pixel with the given offset within the mapping from the universe data
+    ///
+    /// # Important
+    /// Pixels whose channels are not fully contained in the universe data are dropped.
+    pub fn command(&self, offset: usize, data: &[u8], configs: &[StripConfig]) -> Option<Command> {
+        let channels = match configs.get(self.strip)?.rgbw {
+            true => 4,
+            false => 3,
+        };
+        let start = self.channel as usize + (offset * channels);
+
+        // Copy the channels into an RGBW array
+        let mut rgbw = [0; 4];
+        rgbw[..channels].copy_from_slice(data.get(start..start + channels)?);
+        Command::new(self.strip, self.pixel + offset, Color::Rgbw8(rgbw), configs)
+    }
+}
+
+/// The universe mapping table
+///
+/// # Note
+/// If no mapping is configured, each universe is mapped onto 170 RGB pixels, which are distributed over the enabled
+/// strips in ascending order (see `StripConfig::locate`); i.e. universe `0` maps to the LEDs `[0, 170)`, universe `1`
+/// maps to the LEDs `[170, 340)` and so on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniverseMap {
+    /// The configured mappings
+    pub mappings: [Option<UniverseMapping>; Self::SLOTS],
+}
+impl UniverseMap {
+    /// The amount of mapping slots
+    pub const SLOTS: usize = 16;
+    /// The amount of channels per universe
+    pub const CHANNELS: usize = 512;
+    /// The amount of RGB pixels per universe if no mapping is configured
+    pub const LINEAR_PIXELS: usize = Self::CHANNELS / 3;
+
+    /// Creates a new mapping table without any mappings
+    pub const fn new() -> Self {
+        Self { mappings: [None; Self::SLOTS] }
+    }
+
+    /// Decodes the universe data into pixel updates, where invalid updates are dropped
+    pub fn commands<'a>(
+        &'a self,
+        universe: u16,
+        data: &'a [u8],
+        configs: &'a [StripConfig],
+    ) -> impl Iterator<Item = Command> + 'a {
+        // Map the universe linearly if no mapping is configured
+        let linear_pixels = match self.mappings.iter().all(Option::is_none) {
+            true => core::cmp::min(data.len() / 3, Self::LINEAR_PIXELS),
+            false => 0,
+        };
+        let linear = (0..linear_pixels).filter_map(move |offset| {
+            let index = (universe as usize * Self::LINEAR_PIXELS) + offset;
+            let rgb = [data[offset * 3], data[(offset * 3) + 1], data[(offset * 3) + 2]];
+            Command::linear(index, rgb, configs)
+        });
+
+        // Apply all mappings of the universe
+        let mappings = self.mappings.iter().flatten().filter(move |mapping| mapping.universe == universe);
+        let mapped = mappings.flat_map(move |mapping| {
+            (0..mapping.count).filter_map(move |offset| mapping.command(offset, data, configs))
+        });
+        linear.chain(mapped)
+    }
+}
+impl Default for UniverseMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Collects the strip, pixel and RGBW value of all updates
+    fn collect(map: &UniverseMap, universe: u16, data: &[u8], configs: &[StripConfig]) -> Vec<(usize, usize, [u8; 4])> {
+        let commands = map.commands(universe, data, configs);
+        commands.map(|Command { strip, pixel, color }| (strip, pixel, color.to_rgbw16().map(Color::narrow))).collect()
+    }
+
+    #[test]
+    fn universes_are_mapped_linearly_by_default() {
+        let configs = [StripConfig::new(200, false), StripConfig::new(200, false)];
+        let data: Vec<u8> = (0..UniverseMap::CHANNELS).map(|channel| channel as u8).collect();
+
+        let updates = collect(&UniverseMap::new(), 1, &data, &configs);
+        assert_eq!(updates.len(), 170);
+        assert_eq!(updates[0], (0, 170, [0, 1, 2, 0]));
+        assert_eq!(updates[30], (1, 0, [90, 91, 92, 0]));
+        assert_eq!(updates[169], (1, 139, [251, 252, 253, 0]));
+
+        // Universes beyond the strips and incomplete pixels are dropped
+        assert!(collect(&UniverseMap::new(), 3, &data, &configs).is_empty());
+        assert_eq!(collect(&UniverseMap::new(), 0, &data[..8], &configs).len(), 2);
+    }
+
+    #[test]
+    fn mappings_replace_the_linear_mapping() {
+        let configs = [StripConfig::new(10, false), StripConfig::new(10, true)];
+        let mut map = UniverseMap::new();
+        map.mappings[3] = Some(UniverseMapping { universe: 7, channel: 2, strip: 1, pixel: 8, count: 4 });
+        map.mappings[5] = Some(UniverseMapping { universe: 7, channel: 0, strip: 0, pixel: 0, count: 1 });
+
+        // The RGBW strip uses 4 channels per pixel, and pixels beyond the strip are dropped
+        let data = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let updates = collect(&map, 7, &data, &configs);
+        assert_eq!(updates, [(1, 8, [3, 4, 5, 6]), (1, 9, [7, 8, 9, 10]), (0, 0, [1, 2, 3, 0])]);
+        assert!(collect(&map, 0, &data, &configs).is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+//! DMA-driven feeding of the PIO TX FIFOs
+
+use crate::board::hal::dma::single_buffer::{Config, Transfer};
+use crate::board::hal::dma::SingleChannel;
+use crate::board::hal::pio::{PIOExt, StateMachineIndex, Tx, SM0, SM1, SM2, SM3};
+use crate::board::hal::timer::Instant;
+use crate::board::hal::Timer;
+use crate::ws2812b::pio::{PioTx, StripMachine};
+use embedded_dma::ReadBuffer;
+use fugit::MicrosDurationU64;
+use static_cell::ConstStaticCell;
+use ws2812b_driver::config::{StripConfig, Timing};
+
+/// A frame within the static frame pool that is read by the DMA
+struct FrameSlice {
+    /// The pointer to the first word
+    ptr: *const u32,
+    /// The amount of words
+    len: usize,
+}
+// Safety: The slice points into the static frame pool, and the caller of `PioTx::start` guarantees that the frame is not
+// modified while the transfer is running
+unsafe impl ReadBuffer for FrameSlice {
+    type Word = u32;
+
+    unsafe fn read_buffer(&self) -> (*const Self::Word, usize) {
+        (self.ptr, self.len)
+    }
+}
+
+/// The state of a DMA channel
+enum DmaState<CH, P, SM>
+where
+    CH: SingleChannel,
+    P: PIOExt + 'static,
+    SM: StateMachineIndex,
+{
+    /// The DMA channel is idle
+    Idle(CH, Tx<(P, SM)>),
+    /// The DMA channel is transferring a frame
+    Busy(Transfer<CH, FrameSlice, Tx<(P, SM)>>),
+}
+
+/// A DMA channel that feeds a PIO TX FIFO from the static frame pool
+pub struct DmaTx<CH, P, SM>
+where
+    CH: SingleChannel,
+    P: PIOExt + 'static,
+    SM: StateMachineIndex,
+{
+    /// The state machine
+    machine: StripMachine<P, SM>,
+    /// The DMA channel state (which is only `None` during a state transition)
+    state: Option<DmaState<CH, P, SM>>,
+    /// The timer to enforce the latch time
+    timer: Timer,
+    /// The earliest point in time when the next frame may be started
+    latched_at: Instant,
+}
+impl<CH, P, SM> DmaTx<CH, P, SM>
+where
+    CH: SingleChannel,
+    P: PIOExt + 'static,
+    SM: StateMachineIndex,
+{
+    /// Creates a new DMA-fed TX FIFO
+    pub fn new(channel: CH, (machine, tx): (StripMachine<P, SM>, Tx<(P, SM)>), timer: Timer) -> Self {
+        Self { machine, state: Some(DmaState::Idle(channel, tx)), timer, latched_at: Instant::from_ticks(0) }
+    }
+
+    /// Waits until the running transfer is done and returns the idle DMA channel and TX FIFO
+    fn take_idle(&mut self) -> (CH, Tx<(P, SM)>) {
+        match self.state.take() {
+            Some(DmaState::Idle(channel, tx)) => (channel, tx),
+            Some(DmaState::Busy(transfer)) => {
+                let (channel, _, tx) = transfer.wait();
+                (channel, tx)
+            }
+            None => unreachable!("DMA channel is in transition"),
+        }
+    }
+}
+impl<CH, P, SM> PioTx for DmaTx<CH, P, SM>
+where
+    CH: SingleChannel,
+    P: PIOExt + 'static,
+    SM: StateMachineIndex,
+{
+    fn wait(&mut self) {
+        let (channel, tx) = self.take_idle();
+        self.state = Some(DmaState::Idle(channel, tx));
+    }
+
+    unsafe fn start(&mut self, frame: &[u32]) {
+        // Wait until the previous frame has been shifted out and latched
+        // Note: The latch time is measured from the earliest possible end of the previous frame, so that the latch times
+        // of all strips elapse in parallel
+        let (channel, tx) = self.take_idle();
+        self.machine.drain(&tx);
+        while self.timer.get_counter() < self.latched_at {
+            // Hold the line low
+        }
+
+        // Start the transfer and compute the earliest point in time for the next frame
+        let started_at = self.timer.get_counter();
+        let slice = FrameSlice { ptr: frame.as_ptr(), len: frame.len() };
+        let transfer = Config::new(channel, slice, tx).start();
+        let latch_micros = self.machine.frame_micros(frame.len()) + Timing::LATCH_MICROS as u64;
+        self.latched_at = started_at + MicrosDurationU64::micros(latch_micros);
+        self.state = Some(DmaState::Busy(transfer));
+    }
+
+    fn configure(&mut self, config: &StripConfig) {
+        let (channel, tx) = self.take_idle();
+        let tx = self.machine.configure(config, tx);
+        self.state = Some(DmaState::Idle(channel, tx));
+    }
+}
+
+/// Takes the static pool for the encoded frames of all strips
+pub fn frames() -> &'static mut [u32] {
+    /// The pool for the encoded frames of all strips (which is too large to be allocated on the stack)
+    static FRAMES: ConstStaticCell<[u32; StripConfig::COMPILE_ENV_PIXELS]> =
+        ConstStaticCell::new([0; StripConfig::COMPILE_ENV_PIXELS]);
+    FRAMES.take()
+}
+
+/// Wraps the state machines of a PIO engine into DMA-fed TX FIFOs
+#[allow(clippy::type_complexity)]
+pub fn setup<P, CH0, CH1, CH2, CH3>(
+    (ch0, ch1, ch2, ch3): (CH0, CH1, CH2, CH3),
+    timer: Timer,
+    (sm0, sm1, sm2, sm3): (
+        (StripMachine<P, SM0>, Tx<(P, SM0)>),
+        (StripMachine<P, SM1>, Tx<(P, SM1)>),
+        (StripMachine<P, SM2>, Tx<(P, SM2)>),
+        (StripMachine<P, SM3>, Tx<(P, SM3)>),
+    ),
+) -> (DmaTx<CH0, P, SM0>, DmaTx<CH1, P, SM1>, DmaTx<CH2, P, SM2>, DmaTx<CH3, P, SM3>)
+where
+    P: PIOExt + 'static,
+    CH0: SingleChannel,
+    CH1: SingleChannel,
+    CH2: SingleChannel,
+    CH3: SingleChannel,
+{
+    (DmaTx::new(ch0, sm0, timer), DmaTx::new(ch1, sm1, timer), DmaTx::new(ch2, sm2, timer), DmaTx::new(ch3, sm3, timer))
+}
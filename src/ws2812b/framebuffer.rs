@@ -0,0 +1,118 @@
+//! A double-buffered pixel state of all LED strips
+
+use core::cmp;
+use core::ops::Range;
+
+/// A double-buffered pixel state of all LED strips
+///
+/// # Note
+/// All strips share a single pool of `PIXELS` pixels, where each strip occupies a consecutive range that matches its
+/// length. This allows arbitrarily long strips as long as the total amount of pixels fits into RAM.
+pub struct FrameBuffer<const STRIPS: usize, const PIXELS: usize> {
+    /// The back buffer where all updates are written into
+    back: [[u16; 4]; PIXELS],
+    /// The front buffer which is synced to the PIOs
+    front: [[u16; 4]; PIXELS],
+    /// The range of each strip within the pixel pool
+    strips: [Range<usize>; STRIPS],
+    /// The inclusive range of pixels per strip that have been changed in the back buffer since the last latch
+    dirty: [Option<(usize, usize)>; STRIPS],
+}
+impl<const STRIPS: usize, const PIXELS: usize> FrameBuffer<STRIPS, PIXELS> {
+    /// Creates a new empty frame buffer for strips with the given lengths
+    pub const fn new(lens: [usize; STRIPS]) -> Self {
+        let strips = Self::carve(lens).expect("the strips exceed the pixel pool");
+        Self { back: [[0; 4]; PIXELS], front: [[0; 4]; PIXELS], strips, dirty: [None; STRIPS] }
+    }
+
+    /// Sets the strip lengths, where each strip that has been moved or resized is cleared and marked as dirty
+    ///
+    /// # Important
+    /// This function panics if the strips exceed the pixel pool.
+    pub fn set_lens(&mut self, lens: [usize; STRIPS]) {
+        let strips = Self::carve(lens).expect("the strips exceed the pixel pool");
+        for (strip, range) in strips.into_iter().enumerate() {
+            // Skip strips that are unchanged
+            if self.strips[strip] == range {
+                continue;
+            }
+
+            // Clear the strip and mark it as dirty so that the entire strip is refreshed
+            self.back[range.clone()].fill([0; 4]);
+            self.front[range.clone()].fill([0; 4]);
+            self.dirty[strip] = range.len().checked_sub(1).map(|end| (0, end));
+            self.strips[strip] = range;
+        }
+    }
+
+    /// Marks the entire strip as dirty
+    pub fn touch(&mut self, strip: usize) {
+        self.dirty[strip] = self.strips[strip].len().checked_sub(1).map(|end| (0, end));
+    }
+
+    /// The range of the strip within the pixel pool
+    pub fn range(&self, strip: usize) -> Range<usize> {
+        self.strips[strip].clone()
+    }
+
+    /// Gets the latched pixels of the given strip
+    pub fn front(&self, strip: usize) -> &[[u16; 4]] {
+        &self.front[self.range(strip)]
+    }
+
+    /// Writes a pixel into the back buffer and marks it as dirty if it has been changed
+    pub fn update(&mut self, strip: usize, pixel: usize, rgbw: [u16; 4]) {
+        // Get the pixel and skip the update if it is out of range or unchanged
+        let back = &mut self.back[self.strips[strip].clone()];
+        let Some(state) = back.get_mut(pixel) else {
+            return;
+        };
+        if *state == rgbw {
+            return;
+        }
+
+        // Update the pixel and extend the dirty range
+        *state = rgbw;
+        self.dirty[strip] = match self.dirty[strip] {
+            Some((start, end)) => Some((cmp::min(start, pixel), cmp::max(end, pixel))),
+            None => Some((pixel, pixel)),
+        };
+    }
+
+    /// Latches the dirty pixels of the back buffer into the front buffer
+    ///
+    /// # Returns
+    /// The amount of leading pixels per strip that must be sent to the PIO, or `0` if the strip is unchanged. Since
+    /// each pixel is shifted through the entire strip, all pixels up to the last dirty pixel must be sent.
+    pub fn latch(&mut self) -> [usize; STRIPS] {
+        let mut pending = [0; STRIPS];
+        for (strip, pending) in pending.iter_mut().enumerate() {
+            // Copy the dirty range if any
+            let Some((start, end)) = self.dirty[strip].take() else {
+                continue;
+            };
+            let offset = self.strips[strip].start;
+            let dirty = (offset + start)..(offset + end + 1);
+            self.front[dirty.clone()].copy_from_slice(&self.back[dirty]);
+            *pending = end + 1;
+        }
+        pending
+    }
+
+    /// Assigns a consecutive range of the pixel pool to each strip
+    const fn carve(lens: [usize; STRIPS]) -> Option<[Range<usize>; STRIPS]> {
+        let mut strips = [const { 0..0 }; STRIPS];
+        let (mut index, mut offset) = (0, 0);
+        while index < STRIPS {
+            strips[index] = offset..offset + lens[index];
+            offset += lens[index];
+            index += 1;
+        }
+
+        // Ensure that all strips fit into the pool
+        match offset <= PIXELS {
+            true => Some(strips),
+            false => None,
+        }
+    }
+}
@@ -1,21 +1,48 @@
 //! Implements the PIO logic on core 1
 
+mod dma;
+mod framebuffer;
 mod pio;
 
 use crate::board::hal::clocks::SystemClock;
+use crate::board::hal::dma::Channels;
 use crate::board::hal::multicore::Stack;
-use crate::board::hal::Sio;
-use crate::board::pac::Peripherals;
-use crate::command::Command;
+use crate::board::hal::sio::SioFifo;
+use crate::board::hal::{Sio, Timer};
+#[cfg(feature = "pio1")]
+use crate::board::pac::PIO1;
+use crate::board::pac::{Peripherals, PIO0};
+use crate::hardware::flash;
 use crate::hardware::init::Pio0;
+#[cfg(feature = "pio1")]
+use crate::hardware::init::Pio1;
 use crate::hardware::pins::Pio0Pins;
-use crate::ws2812b::pio::PioTx;
+#[cfg(feature = "pio1")]
+use crate::hardware::pins::Pio1Pins;
+use crate::ws2812b::framebuffer::FrameBuffer;
+use crate::ws2812b::pio::{PioBlock, PioTx};
 use core::cell::RefCell;
-use core::hint;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering::Relaxed;
 use critical_section::Mutex;
+use static_cell::ConstStaticCell;
+use ws2812b_driver::brightness::Brightness;
+use ws2812b_driver::command::Command;
+use ws2812b_driver::config::StripConfig;
+use ws2812b_driver::const_int_from_compileenv;
+use ws2812b_driver::dither::Dither;
+use ws2812b_driver::gamma::Gamma;
+use ws2812b_driver::message::Message;
+use ws2812b_driver::power::PowerBudget;
+use ws2812b_driver::ring::{Consumer, Producer, Ring};
 
-/// The state matrix of an LED strip
-type StripState<const SIZE: usize> = [Option<(u8, u8, u8)>; SIZE];
+/// Whether the immediate mode is enabled by default, where every update is shown without an explicit `Show`
+const IMMEDIATE_MODE: bool = const_int_from_compileenv!("WS2812B_IMMEDIATE_MODE" => u8, default: "0") != 0;
+
+/// The size of the inter-core ring buffer in words (16 KiB)
+pub const IPC_RING_SIZE: usize = 4096;
+/// The inter-core ring buffer
+pub type IpcRing = Ring<IPC_RING_SIZE>;
 
 /// The required hardware for core 1
 pub struct Core1Hardware {
@@ -25,10 +52,83 @@ pub struct Core1Hardware {
     pub pio0: Pio0,
     /// The PIO0 associated pins
     pub pio0_pins: Pio0Pins,
+    /// The PIO1 peripheral
+    #[cfg(feature = "pio1")]
+    pub pio1: Pio1,
+    /// The PIO1 associated pins
+    #[cfg(feature = "pio1")]
+    pub pio1_pins: Pio1Pins,
+    /// The DMA channels
+    pub dma: Channels,
+    /// The timer to enforce the latch time between frames
+    pub timer: Timer,
+    /// The consumer half of the inter-core ring buffer
+    pub consumer: Consumer<'static, IPC_RING_SIZE>,
+    /// The initial strip configurations
+    pub configs: [StripConfig; StripConfig::STRIPS],
+    /// The upper limit for the effective brightness
+    pub brightness_limit: u8,
 }
+/// The estimated current of each strip in mA after the last refresh
+static CURRENTS: [AtomicU32; StripConfig::STRIPS] = [const { AtomicU32::new(0) }; StripConfig::STRIPS];
+
 /// The hardware required by core 1
 pub static CORE1_HARDWARE: Mutex<RefCell<Option<Core1Hardware>>> = Mutex::new(RefCell::new(None));
 
+/// Sends messages to core 1 via the inter-core ring buffer
+pub struct Core1Sender {
+    /// The producer half of the inter-core ring buffer
+    producer: Producer<'static, IPC_RING_SIZE>,
+    /// The inter-core FIFO which is used as doorbell to wake up core 1
+    sio_fifo: SioFifo,
+}
+impl Core1Sender {
+    /// Creates a new sender from the producer half of the inter-core ring buffer and the inter-core FIFO
+    pub const fn new(producer: Producer<'static, IPC_RING_SIZE>, sio_fifo: SioFifo) -> Self {
+        Self { producer, sio_fifo }
+    }
+
+    /// Sends a message to core 1
+    pub async fn send(&mut self, message: Message) {
+        // Wait until the ring buffer has enough space available
+        let (words, len) = message.to_words();
+        while !self.producer.push(&words[..len]) {
+            // Always yield here to avoid a tight loop
+            embedded_runtime_rp2040::spin_once().await;
+        }
+
+        // Ring the doorbell
+        // Note: If the FIFO is full, core 1 has pending doorbells and will wake up anyway
+        if self.sio_fifo.is_write_ready() {
+            self.sio_fifo.write(0);
+        }
+    }
+
+    /// Parks core 1 in RAM while the given function is running, so that the function can safely access the flash
+    pub async fn with_core1_parked<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        // Request core 1 to park and wait until it is parked
+        flash::request_park();
+        self.send(Message::Park).await;
+        while !flash::is_parked() {
+            // Always yield here to avoid a tight loop
+            embedded_runtime_rp2040::spin_once().await;
+        }
+
+        // Run the function and release core 1
+        let result = f();
+        flash::unpark();
+        result
+    }
+}
+
+/// Gets the estimated current of each strip in mA after the last refresh
+pub fn currents() -> [u32; StripConfig::STRIPS] {
+    core::array::from_fn(|strip| CURRENTS[strip].load(Relaxed))
+}
+
 /// Returns the stack for core 1
 pub fn stack_core1() -> &'static mut [usize; 1024 * 12] {
     /// The stack for the core 1 (48 KiB)
@@ -37,7 +137,7 @@ pub fn stack_core1() -> &'static mut [usize; 1024 * 12] {
     (unsafe { &mut STACK.mem })
 }
 
-/// A tight runloop that checks the inter-core FIFO for pixel changes and syncs the new state to the PIO
+/// A tight runloop that checks the inter-core ring buffer for pixel changes and syncs the new state to the PIO
 ///
 /// # Important
 /// This runloop is blocking and designed to run on another core exclusively (i.e. core 1)
@@ -46,49 +146,183 @@ pub fn write_pio_core1() {
     // This should hopefully be safe since the SIO FIFO is explicitely designed for inter-core communication
     let Peripherals { SIO, .. } = unsafe { Peripherals::steal() };
     let Sio { mut fifo, .. } = Sio::new(SIO);
-    let Core1Hardware { system_clock, pio0, pio0_pins } =
+    let hardware =
         critical_section::with(|cs| CORE1_HARDWARE.take(cs)).expect("missing required hardware instances for core 1");
+    let mut consumer = hardware.consumer;
 
     // Init states and setup state machines
-    let mut states: [StripState<512>; 4] = [[None; 512]; 4];
-    let (mut _tx0, mut _tx1, mut _tx2, mut _tx3) = pio::setup(pio0, pio0_pins, &system_clock);
-    let pio_tx: [&mut dyn PioTx; 4] = [&mut _tx0, &mut _tx1, &mut _tx2, &mut _tx3];
+    /// The frame buffer (which is too large to be allocated on the stack)
+    static STATES: ConstStaticCell<FrameBuffer<{ StripConfig::STRIPS }, { StripConfig::COMPILE_ENV_PIXELS }>> =
+        ConstStaticCell::new(FrameBuffer::new([0; StripConfig::STRIPS]));
+    let mut configs = hardware.configs;
+    let states = STATES.take();
+    states.set_lens(configs.map(|config| config.len));
+    let frames = dma::frames();
+    let mut immediate = IMMEDIATE_MODE;
+    let mut brightness = Brightness::new(hardware.brightness_limit);
+    let mut gamma = Gamma::new(Gamma::COMPILE_ENV);
+    let mut budget = PowerBudget::COMPILE_ENV;
+    let mut currents = [0; StripConfig::STRIPS];
+    let mut levels = [u8::MAX; StripConfig::STRIPS];
+    let mut dither = Dither::new(Dither::COMPILE_ENV);
+
+    // Setup the state machines of PIO0
+    /// The PIO0 block, which is shared by its state machines
+    static PIO0_BLOCK: PioBlock<PIO0> = PioBlock::new();
+    let pio0_configs = configs.first_chunk().expect("missing strip configurations for PIO0");
+    let pio0_sm = pio::setup(hardware.pio0, &PIO0_BLOCK, hardware.pio0_pins, pio0_configs, &hardware.system_clock);
+    let Channels { ch0, ch1, ch2, ch3, .. } = hardware.dma;
+    let (mut tx0, mut tx1, mut tx2, mut tx3) = dma::setup((ch0, ch1, ch2, ch3), hardware.timer, pio0_sm);
+
+    // Setup the state machines of PIO1 if enabled
+    #[cfg(feature = "pio1")]
+    let (mut tx4, mut tx5, mut tx6, mut tx7) = {
+        /// The PIO1 block, which is shared by its state machines
+        static PIO1_BLOCK: PioBlock<PIO1> = PioBlock::new();
+        let pio1_configs = configs[4..].first_chunk().expect("missing strip configurations for PIO1");
+        let pio1_sm = pio::setup(hardware.pio1, &PIO1_BLOCK, hardware.pio1_pins, pio1_configs, &hardware.system_clock);
+        let Channels { ch4, ch5, ch6, ch7, .. } = hardware.dma;
+        dma::setup((ch4, ch5, ch6, ch7), hardware.timer, pio1_sm)
+    };
+
+    // Collect the TX FIFOs of all strips
+    #[cfg(not(feature = "pio1"))]
+    let mut pio_tx: [&mut dyn PioTx; StripConfig::STRIPS] = [&mut tx0, &mut tx1, &mut tx2, &mut tx3];
+    #[cfg(feature = "pio1")]
+    let mut pio_tx: [&mut dyn PioTx; StripConfig::STRIPS] =
+        [&mut tx0, &mut tx1, &mut tx2, &mut tx3, &mut tx4, &mut tx5, &mut tx6, &mut tx7];
 
     // Loop forever to process the incoming state
     loop {
-        // Wait until we receive an update
-        while !fifo.is_read_ready() {
-            hint::spin_loop();
+        // Sleep until we receive a doorbell if there are no pending messages, and drain all pending doorbells
+        // Note: The dithering refreshes the strips continuously, so we must not sleep if it is enabled
+        if consumer.is_empty() && !dither.enabled {
+            fifo.read_blocking();
+        }
+        while fifo.read().is_some() {
+            // Drain the FIFO
         }
 
-        // Update the state (the FIFO can store at max 8 entries, so we limit ourself to 8 to avoid stalling the PIO)
-        'read_fifo: for _ in 0..8 {
-            // Read the next packed update
-            let Some(packed) = fifo.read() else {
-                break 'read_fifo;
+        // Update the state
+        let mut show = false;
+        'read_ring: while let Some(header) = consumer.pop() {
+            // Read the next message
+            // Note: Messages are published atomically, so the payload words must be available
+            let next = || consumer.pop().expect("incomplete inter-core message");
+            let Some(message) = Message::from_words(header, next) else {
+                continue 'read_ring;
             };
 
-            // Update the state
-            let Command { strip, pixel, rgb } = Command::from_u32(packed);
-            states[strip][pixel] = Some(rgb);
+            // Apply the message
+            match message {
+                Message::Update(Command { strip, pixel, color }) => states.update(strip, pixel, color.to_rgbw16()),
+                Message::SetImmediate(enabled) => immediate = enabled,
+                Message::Configure(strip, config) => {
+                    // Wait until all transfers are done since the strips may be moved within the frame pool
+                    for tx in pio_tx.iter_mut() {
+                        tx.wait();
+                    }
+
+                    // Apply the configuration and refresh the entire strip with the next latch
+                    if !StripConfig::reconfigure(&mut configs, strip, config) {
+                        continue 'read_ring;
+                    }
+                    states.set_lens(configs.map(|config| config.len));
+                    states.touch(strip);
+                    pio_tx[strip].configure(&config);
+                }
+                Message::SetBrightness(strip, level) => {
+                    // Refresh all affected strips with the next latch
+                    brightness.set(strip, level);
+                    for index in 0..StripConfig::STRIPS {
+                        if strip.is_none() || strip == Some(index) {
+                            states.touch(index);
+                        }
+                    }
+                }
+                Message::SetCurve(curve) => {
+                    // Refresh all strips with the next latch
+                    gamma.curve = curve;
+                    for strip in 0..StripConfig::STRIPS {
+                        states.touch(strip);
+                    }
+                }
+                Message::SetGammaEntry(index, value) => gamma.custom[index as usize] = value,
+                Message::SetPowerBudget(strip, ma) => budget.set(strip, ma),
+                Message::SetDither(enabled) => {
+                    // Refresh all strips with the next latch to remove the dithered levels
+                    dither.enabled = enabled;
+                    for strip in 0..StripConfig::STRIPS {
+                        states.touch(strip);
+                    }
+                }
+                Message::Park => flash::park(),
+                Message::Show => {
+                    // Stop reading to not mix subsequent updates into the latched frame
+                    show = true;
+                    break 'read_ring;
+                }
+            }
         }
 
-        // Sync to all PIOs
-        'write_strip: for strip in 0..states.len() {
-            #[allow(clippy::needless_range_loop, reason = "readability")]
-            for pixel in 0..states[strip].len() {
-                // Skip the strip if the pixel index is beyond end-of-strip
-                let Some((r, g, b)) = states[strip][pixel] else {
-                    continue 'write_strip;
-                };
-
-                // Write the pixel
-                // Note: This "weird" encoding is intentional since the LED strip/state machine requires this order
-                let grb = ((g as u32) << 24) | ((r as u32) << 16) | ((b as u32) << 8);
-                while !pio_tx[strip].write(grb) {
-                    hint::spin_loop();
+        // Latch the back buffer if appropriate, or refresh the latched state if the dithering is enabled
+        let mut pending = match (show || immediate, dither.enabled) {
+            (true, _) => states.latch(),
+            (false, true) => [0; StripConfig::STRIPS],
+            (false, false) => continue,
+        };
+
+        // Estimate the currents of the changed strips at full brightness
+        // Note: The current of a strip only changes if its pixels, its configuration or the gamma curve has changed,
+        // and all of them mark the strip as pending
+        for (strip, current) in currents.iter_mut().enumerate() {
+            let config = configs[strip];
+            match (pending[strip], config.enabled) {
+                (_, false) => *current = 0,
+                (0, true) => (/* current is unchanged */),
+                (_, true) => {
+                    *current = budget.estimate(states.front(strip).iter().map(|rgbw| gamma.apply(*rgbw)), config.rgbw)
                 }
             }
         }
+
+        // Limit the brightness to the power budget, and refresh the entire strip if its effective brightness changes
+        let (limited, limited_currents) = budget.limit(currents, core::array::from_fn(|strip| brightness.level(strip)));
+        for strip in 0..StripConfig::STRIPS {
+            if limited[strip] != levels[strip] {
+                pending[strip] = states.range(strip).len();
+            }
+            CURRENTS[strip].store(limited_currents[strip], Relaxed);
+        }
+        levels = limited;
+
+        // Refresh all strips with the next dithering phase
+        if dither.enabled {
+            dither.advance();
+            for (strip, pending) in pending.iter_mut().enumerate() {
+                *pending = states.range(strip).len();
+            }
+        }
+
+        // Sync the changed strips to their PIOs
+        // Note: The DMA transfers run in parallel, so we only wait if the previous frame of a strip is still in flight
+        for (strip, pending) in pending.into_iter().enumerate() {
+            // Skip unchanged or disabled strips
+            if pending == 0 || !configs[strip].enabled {
+                continue;
+            }
+
+            // Encode the corrected, dimmed and dithered pixels into the frame once the previous frame has been transferred
+            pio_tx[strip].wait();
+            let frame = &mut frames[states.range(strip)][..pending];
+            for (pixel, (word, rgbw)) in frame.iter_mut().zip(states.front(strip)).enumerate() {
+                let rgbw = Brightness::apply(gamma.apply(*rgbw), levels[strip]);
+                *word = configs[strip].encode(dither.apply(rgbw, pixel));
+            }
+
+            // Start the transfer
+            // Safety: The frame is only modified again after the next `wait`
+            unsafe { pio_tx[strip].start(frame) };
+        }
     }
 }
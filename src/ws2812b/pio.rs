@@ -1,114 +1,211 @@
 //! The PIO assembly for WS2812B
 
 use crate::board::hal::clocks::SystemClock;
-use crate::board::hal::pio::{PIOBuilder, PinDir, ShiftDirection, Tx, SM0, SM1, SM2, SM3};
+use crate::board::hal::gpio::Function;
+use crate::board::hal::pio::{
+    PIOBuilder, PIOExt, PinDir, Running, Rx, ShiftDirection, StateMachine, StateMachineIndex, Stopped, Tx,
+    UninitStateMachine, PIO, SM0, SM1, SM2, SM3,
+};
 use crate::board::hal::Clock;
-use crate::board::pac::PIO0;
-use crate::hardware::init::Pio0;
-use crate::hardware::pins::Pio0Pins;
+use crate::hardware::init::PioEngine;
+use crate::hardware::pins::PioPins;
+use core::cell::RefCell;
 use pio::{Program, RP2040_MAX_PROGRAM_SIZE};
+use static_cell::StaticCell;
+use ws2812b_driver::config::{PioTiming, StripConfig};
 
-/// A PIO TX pin
+/// A PIO block that is shared by its state machines to (re)install their programs
+pub type PioBlock<P> = StaticCell<RefCell<PIO<P>>>;
+
+/// A PIO TX FIFO that is fed from a frame buffer
 pub trait PioTx {
-    /// Writes a value to the TX FIFO handle
-    fn write(&mut self, value: u32) -> bool;
+    /// Waits until the previous frame has been transferred to the TX FIFO
+    fn wait(&mut self);
+    /// Starts the transfer of the frame to the TX FIFO
+    ///
+    /// # Safety
+    /// The frame must neither be modified nor dropped until the transfer is done (see `wait`).
+    unsafe fn start(&mut self, frame: &[u32]);
+    /// Waits until the previous frame has been shifted out and applies the given configuration to the state machine
+    fn configure(&mut self, config: &StripConfig);
+}
+
+/// The state of a state machine
+enum MachineState<P, SM>
+where
+    P: PIOExt + 'static,
+    SM: StateMachineIndex,
+{
+    /// The state machine is running
+    Running(StateMachine<(P, SM), Running>),
+    /// The state machine is stopped
+    Stopped(StateMachine<(P, SM), Stopped>),
 }
-/// Implements `PioTx` for the given type
-macro_rules! impl_pio_tx {
-    ($type:ty) => {
-        impl PioTx for $type {
-            fn write(&mut self, value: u32) -> bool {
-                self.write(value)
-            }
+
+/// A WS2812B state machine that can be reconfigured at runtime
+pub struct StripMachine<P, SM>
+where
+    P: PIOExt + 'static,
+    SM: StateMachineIndex,
+{
+    /// The PIO block to (re)install the program
+    pio: &'static RefCell<PIO<P>>,
+    /// The state machine (which is only `None` during a reconfiguration)
+    state: Option<MachineState<P, SM>>,
+    /// The RX FIFO of the state machine (which is only `None` during a reconfiguration)
+    rx: Option<Rx<(P, SM)>>,
+    /// The output pin
+    pin: u8,
+    /// The current strip configuration
+    config: StripConfig,
+    /// The system clock frequency
+    clock_frequency: u32,
+}
+impl<P, SM> StripMachine<P, SM>
+where
+    P: PIOExt + 'static,
+    SM: StateMachineIndex,
+{
+    /// Creates and starts a new state machine with the given configuration
+    fn new(
+        pio: &'static RefCell<PIO<P>>,
+        sm: UninitStateMachine<(P, SM)>,
+        pin: u8,
+        config: &StripConfig,
+        clock_frequency: u32,
+    ) -> (Self, Tx<(P, SM)>) {
+        let mut this = Self { pio, state: None, rx: None, pin, config: *config, clock_frequency };
+        let tx = this.build(sm, config);
+        (this, tx)
+    }
+
+    /// The minimum time in microseconds to shift out a frame with the given amount of pixels
+    pub fn frame_micros(&self, pixels: usize) -> u64 {
+        let timing = self.config.timing.pio_timing(self.clock_frequency);
+        timing.frame_micros(pixels * self.config.bits_per_pixel() as usize, self.clock_frequency)
+    }
+
+    /// Waits until the state machine has shifted out the last pixel and stalls on the empty TX FIFO
+    pub fn drain(&self, tx: &Tx<(P, SM)>) {
+        // Stopped state machines don't shift out anything
+        let Some(MachineState::Running(_)) = &self.state else {
+            return;
+        };
+
+        // Wait until the state machine stalls on an empty TX FIFO
+        while !tx.is_empty() {
+            // Wait for the FIFO to drain
         }
-    };
+        tx.clear_stalled_flag();
+        while !tx.has_stalled() {
+            // Wait for the last pixel
+        }
+    }
+
+    /// Waits until the TX FIFO has been shifted out and applies the given configuration
+    pub fn configure(&mut self, config: &StripConfig, tx: Tx<(P, SM)>) -> Tx<(P, SM)> {
+        // Stop the state machine once the last pixel has been shifted out
+        self.drain(&tx);
+        let (state, rx) = self.state.take().zip(self.rx.take()).expect("state machine is not initialized");
+        let sm = match state {
+            MachineState::Running(sm) => sm.stop(),
+            MachineState::Stopped(sm) => sm,
+        };
+
+        // Uninstall the program and rebuild the state machine with the new configuration
+        let (sm, program) = sm.uninit(rx, tx);
+        self.pio.borrow_mut().uninstall(program);
+        self.build(sm, config)
+    }
+
+    /// Installs the program, builds the state machine with the given configuration and starts it if the strip is
+    /// enabled
+    fn build(&mut self, sm: UninitStateMachine<(P, SM)>, config: &StripConfig) -> Tx<(P, SM)> {
+        // Derive the clock divider and install the program with the appropriate delays
+        let timing = config.timing.pio_timing(self.clock_frequency);
+        let program = self.pio.borrow_mut().install(&program(&timing)).expect("failed to install program");
+
+        // Setup state machine
+        let (mut sm, rx, tx) = PIOBuilder::from_installed_program(program)
+            .side_set_pin_base(self.pin)
+            .out_shift_direction(ShiftDirection::Left)
+            .autopull(true)
+            .pull_threshold(config.bits_per_pixel())
+            .clock_divisor_fixed_point(timing.divider_int, timing.divider_frac)
+            .build(sm);
+
+        // Set pin direction and start the state machine if appropriate
+        sm.set_pindirs([(self.pin, PinDir::Output)]);
+        let state = match config.enabled {
+            true => MachineState::Running(sm.start()),
+            false => MachineState::Stopped(sm),
+        };
+        self.state = Some(state);
+        self.rx = Some(rx);
+        self.config = *config;
+        tx
+    }
 }
-impl_pio_tx!(Tx<(PIO0, SM0)>);
-impl_pio_tx!(Tx<(PIO0, SM1)>);
-impl_pio_tx!(Tx<(PIO0, SM2)>);
-impl_pio_tx!(Tx<(PIO0, SM3)>);
 
-/// Deploys the assembly code to the PIO
+/// Moves the PIO engine into the given shared block and sets up a state machine for each pin
 ///
-/// # Safety
-/// The deployed program must not be uninstalled until all state machines have been stopped manually.
+/// # Note
+/// Every state machine installs its own program, since the delays of the program depend on the timing profile of the
+/// strip.
 #[allow(clippy::type_complexity)]
-pub fn setup(
-    pio0: Pio0,
-    pio0_pins: Pio0Pins,
+pub fn setup<P, F>(
+    engine: PioEngine<P>,
+    block: &'static PioBlock<P>,
+    pins: PioPins<F>,
+    configs: &[StripConfig; 4],
     system_clock: &SystemClock,
-) -> (Tx<(PIO0, SM0)>, Tx<(PIO0, SM1)>, Tx<(PIO0, SM2)>, Tx<(PIO0, SM3)>) {
-    /// The WS2812B frequency (800 kHz)
-    const WS2812B_FREQUENCY: u32 = 800_000;
-    /// The amount of PIO clock cycles per control bit
-    const CYCLES_PER_CONTROL_BIT: u32 = 10;
-
-    // Compute clock frequency
+) -> (
+    (StripMachine<P, SM0>, Tx<(P, SM0)>),
+    (StripMachine<P, SM1>, Tx<(P, SM1)>),
+    (StripMachine<P, SM2>, Tx<(P, SM2)>),
+    (StripMachine<P, SM3>, Tx<(P, SM3)>),
+)
+where
+    P: PIOExt + 'static,
+    F: Function,
+{
+    // Share the PIO block
+    let PioEngine { pio, sm0, sm1, sm2, sm3 } = engine;
+    let pio: &'static RefCell<PIO<P>> = block.init(RefCell::new(pio));
     let clock_frequency = system_clock.freq().to_Hz();
-    let target_frequency = WS2812B_FREQUENCY * CYCLES_PER_CONTROL_BIT;
-    let (frequency_int, frequency_frac) = frequency(clock_frequency, target_frequency);
-
-    // Install the programm
-    let Pio0 { mut pio, sm0, sm1, sm2, sm3 } = pio0;
-    let program = program();
-    let installed = pio.install(&program).expect("failed to install program");
-
-    // Setup the state machines
-    macro_rules! setup_statemachine {
-        ($sm:expr => $pin:expr) => {{
-            // Setup state machine
-            let (mut sm, _, tx) = PIOBuilder::from_installed_program(unsafe { installed.share() })
-                .side_set_pin_base($pin.id().num)
-                .out_shift_direction(ShiftDirection::Left)
-                .autopull(true)
-                .pull_threshold(24)
-                .clock_divisor_fixed_point(frequency_int, frequency_frac)
-                .build($sm);
-
-            // Set pin direction
-            sm.set_pindirs([($pin.id().num, PinDir::Output)]);
-            sm.start();
-            tx
-        }};
-    }
 
     // Create the state machine tuple
     (
-        setup_statemachine!(sm0 => pio0_pins.pin_a),
-        setup_statemachine!(sm1 => pio0_pins.pin_b),
-        setup_statemachine!(sm2 => pio0_pins.pin_c),
-        setup_statemachine!(sm3 => pio0_pins.pin_d),
+        StripMachine::new(pio, sm0, pins.pin_a.id().num, &configs[0], clock_frequency),
+        StripMachine::new(pio, sm1, pins.pin_b.id().num, &configs[1], clock_frequency),
+        StripMachine::new(pio, sm2, pins.pin_c.id().num, &configs[2], clock_frequency),
+        StripMachine::new(pio, sm3, pins.pin_d.id().num, &configs[3], clock_frequency),
     )
 }
 
-/// Computes the clock frequency
-const fn frequency(clock_frequency: u32, target_frequency: u32) -> (u16, u8) {
-    // Compute frequency
-    let int = clock_frequency / target_frequency;
-    let rem = clock_frequency - (int * target_frequency);
-    let frac = (rem * 256) / target_frequency;
-
-    // 65536.0 is represented as 0 in the PIO's clock divider
-    match int {
-        65536 => (0, frac as u8),
-        _ => (int as u16, frac as u8),
-    }
-}
-
-/// The WS2812B program
-fn program() -> Program<{ RP2040_MAX_PROGRAM_SIZE }> {
+/// The WS2812B program with the delays of the given timing
+fn program(timing: &PioTiming) -> Program<{ RP2040_MAX_PROGRAM_SIZE }> {
     let compiled = pio_proc::pio_asm! {
         ".side_set 1",
         ".wrap_target",
         // Bitloop
         "bitloop:",
-        "   out x  1       side 0 [3 - 1]",
-        "   jmp !x do_zero side 1 [2 - 1]",
-        "   jmp    bitloop side 1 [5 - 1]",
+        "   out x  1       side 0",
+        "   jmp !x do_zero side 1",
+        "   jmp    bitloop side 1",
         // Do zero
         "do_zero:",
-        "   nop            side 0 [5 - 1]",
+        "   nop            side 0",
         ".wrap"
     };
-    compiled.program
+
+    // Patch the delays: the leading low phase, the high phase of a zero bit, and the additional high or low phase
+    // Note: The delay field is located at the bits `[8, 12)` since one bit of the delay/side-set field is used for
+    // side-set
+    let [low, high, extra] = timing.cycles;
+    let mut program = compiled.program;
+    for (instruction, cycles) in program.code.iter_mut().zip([low, high, extra, extra]) {
+        *instruction = (*instruction & !0x0F00) | (((cycles - 1) as u16) << 8);
+    }
+    program
 }